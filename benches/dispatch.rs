@@ -0,0 +1,208 @@
+// Measures how fast the parser gets through long chains of operators, where
+// most of the time goes into looking up parselets and binding powers. Every
+// input is parsed with the Bantam grammar as it is, with parselets looked up
+// in arrays, with the names and operators turned into declared operators,
+// which are looked up in HashMaps like everything used to be, and with the
+// same parselets registered through register_prefix()/register_infix(),
+// which calls them through `dyn` instead of the PrefixEntry/InfixEntry enums.
+//
+// Run with `cargo bench --bench dispatch`. The input is lexed up front so
+// only the parser is measured. The grammars take turns, RUNS times over, and
+// the median time per parse is printed.
+
+use std::{hint::black_box, time::Instant};
+
+use bantam::{
+    core::{Lexer, Precedence, Token, TokenType},
+    grammar::Grammar,
+    parselet::{
+        AssignParselet, BinaryOperatorParselet, BlockParselet, CallParselet, ConditionalParselet,
+        GroupParselet, LetParselet, MatchParselet, NameParselet, NumberParselet,
+        OptionalMemberParselet, PipelineParselet, PostfixOperatorParselet, PrefixOperatorParselet,
+    },
+};
+
+const OPERANDS: usize = 10_000;
+const ROUNDS: usize = 50;
+const RUNS: usize = 5;
+
+fn lex(input: &str) -> Vec<Token<'_>> {
+    Lexer::new(input).collect()
+}
+
+// Names can only be made of letters, so count in base 26: xa, xb, ..., xba,
+// xbb, ... The x keeps them from running into keywords like `in`
+fn name(mut i: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'a' + (i % 26) as u8);
+        i /= 26;
+        if i == 0 {
+            break;
+        }
+    }
+
+    name.push(b'x');
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+// The kinds of token in the inputs that no parselet checks for by kind, so
+// they can be swapped for declared operators
+const REMAPPED: [TokenType; 6] = [
+    TokenType::Name,
+    TokenType::Plus,
+    TokenType::Minus,
+    TokenType::Asterisk,
+    TokenType::Slash,
+    TokenType::Bang,
+];
+
+// The declared operator that stands in for `tt` in the HashMap case
+fn declared(tt: TokenType) -> TokenType {
+    let symbol = match tt {
+        TokenType::Name => "@",
+        TokenType::Plus => "@+",
+        TokenType::Minus => "@-",
+        TokenType::Asterisk => "@*",
+        TokenType::Slash => "@/",
+        TokenType::Bang => "@!",
+        _ => return tt,
+    };
+    TokenType::operator(symbol).unwrap()
+}
+
+// Grammar::bantam() with the parselets for REMAPPED registered under their
+// declared() kinds too. Declared operators have no index(), so the parser
+// looks them and their binding powers up in HashMaps
+fn hashed_bantam() -> Grammar {
+    let bantam = Grammar::bantam();
+    let mut grammar = bantam.clone();
+    for tt in REMAPPED {
+        if let Some(prefix) = bantam.get_prefix(tt) {
+            grammar.register_prefix_entry(declared(tt), prefix.clone());
+        }
+        if let Some(infix) = bantam.get_infix(tt) {
+            grammar.register_infix_entry(declared(tt), infix.clone());
+        }
+    }
+
+    grammar
+}
+
+// Grammar::bantam() with every parselet a Custom one
+fn dyn_bantam() -> Grammar {
+    let mut grammar = Grammar::new();
+
+    grammar.register_prefix(TokenType::Name, Box::new(NameParselet::new()));
+    grammar.register_prefix(TokenType::Number, Box::new(NumberParselet::new()));
+    grammar.register_prefix(TokenType::Let, Box::new(LetParselet::new()));
+    grammar.register_prefix(TokenType::LeftBrace, Box::new(BlockParselet::new()));
+    grammar.register_prefix(TokenType::Match, Box::new(MatchParselet::new()));
+    grammar.register_prefix(TokenType::LeftParen, Box::new(GroupParselet::new()));
+    grammar.register_infix(TokenType::Pipe, Box::new(PipelineParselet::new()));
+    grammar.register_infix(TokenType::Assign, Box::new(AssignParselet::new()));
+    grammar.register_infix(TokenType::Question, Box::new(ConditionalParselet::new()));
+    grammar.register_infix(TokenType::LeftParen, Box::new(CallParselet::new()));
+    grammar.register_infix(
+        TokenType::QuestionDot,
+        Box::new(OptionalMemberParselet::new()),
+    );
+
+    for tt in [
+        TokenType::Plus,
+        TokenType::Minus,
+        TokenType::Tilde,
+        TokenType::Bang,
+    ] {
+        let parselet = PrefixOperatorParselet::new(Precedence::Prefix);
+        grammar.register_prefix(tt, Box::new(parselet));
+    }
+    let postfix = PostfixOperatorParselet::new(Precedence::Postfix);
+    grammar.register_infix(TokenType::Bang, Box::new(postfix));
+
+    let binary = [
+        (TokenType::QuestionQuestion, Precedence::Coalesce, true),
+        (TokenType::DotDot, Precedence::Range, false),
+        (TokenType::DotDotEqual, Precedence::Range, false),
+        (TokenType::Plus, Precedence::Sum, false),
+        (TokenType::Minus, Precedence::Sum, false),
+        (TokenType::Asterisk, Precedence::Product, false),
+        (TokenType::Slash, Precedence::Product, false),
+        (TokenType::Caret, Precedence::Exponent, true),
+    ];
+    for (tt, precedence, is_right) in binary {
+        let parselet = BinaryOperatorParselet::new(precedence, is_right);
+        grammar.register_infix(tt, Box::new(parselet));
+    }
+
+    grammar
+}
+
+// The grammars come with whether they parse the declared() tokens
+fn bench(name: &str, grammars: &[(&str, Grammar, bool)], input: &str) {
+    let tokens = lex(input);
+    let declared_tokens: Vec<Token> = tokens
+        .iter()
+        .map(|token| {
+            let tt = declared(*token.get_type());
+            Token::with_span(tt, token.get_text(), token.get_span())
+        })
+        .collect();
+
+    let mut times = vec![Vec::new(); grammars.len()];
+    for _ in 0..RUNS {
+        for ((_, grammar, is_declared), times) in grammars.iter().zip(&mut times) {
+            let tokens = if *is_declared {
+                &declared_tokens
+            } else {
+                &tokens
+            };
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                let mut parser = grammar.parser(tokens.clone());
+                black_box(parser.parse_expression());
+            }
+            times.push(start.elapsed().as_secs_f64() / ROUNDS as f64);
+        }
+    }
+
+    for ((lookup, _, _), mut times) in grammars.iter().zip(times) {
+        times.sort_by(f64::total_cmp);
+        let per_parse = times[RUNS / 2];
+        println!(
+            "{:<8} {:<8} {:>8.2} ms/parse {:>12.0} tokens/s",
+            name,
+            lookup,
+            per_parse * 1000.0,
+            tokens.len() as f64 / per_parse
+        );
+    }
+}
+
+fn main() {
+    let grammars = [
+        ("arrays", Grammar::bantam(), false),
+        ("HashMap", hashed_bantam(), true),
+        ("dyn", dyn_bantam(), false),
+    ];
+    let operators = ["+", "*", "-", "/"];
+
+    // xa + xb * xc - xd / ...
+    let mut mixed = name(0);
+    for i in 1..OPERANDS {
+        mixed.push_str(&format!(" {} {}", operators[i % operators.len()], name(i)));
+    }
+    bench("mixed", &grammars, &mixed);
+
+    // xa + xb + xc + ..., which never recurses
+    let sum = (0..OPERANDS).map(name).collect::<Vec<_>>().join(" + ");
+    bench("sum", &grammars, &sum);
+
+    // Prefix operators and calls in between the binary ones
+    let mut calls = String::from("f(a)");
+    for i in 1..OPERANDS / 2 {
+        calls.push_str(&format!(" * -f({}, b)!", name(i)));
+    }
+    bench("calls", &grammars, &calls);
+}
@@ -0,0 +1,410 @@
+use std::ops::Range;
+
+use crate::{
+    builder::ExprBuilder,
+    core::TokenType,
+    expression::{Expr, SourceSpan},
+    pattern::Pattern,
+};
+
+// Identifies a node in an Arena. Ids are handed out in order starting at 0,
+// so side tables can be plain Vecs indexed by ExprId::index()
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExprId(u32);
+
+impl ExprId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// What a node is, without its children. The children are in Arena::children
+// in the order listed here
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExprKind {
+    Name(String),
+    Number(String),
+    // right
+    Prefix(TokenType),
+    // left, right
+    Binary(TokenType),
+    // left
+    Postfix(TokenType),
+    // condition, then arm, else arm
+    Conditional,
+    // right
+    Assign(String),
+    // function, then the arguments
+    Call,
+    // the elements
+    Tuple,
+    // body
+    Lambda(Vec<String>),
+    // left if there is one, then right if there is one
+    Section {
+        operator: TokenType,
+        left: bool,
+        right: bool,
+    },
+    // object
+    OptionalMember(String),
+    // function, then the arguments
+    OptionalCall,
+    // value, function
+    Pipeline,
+    // value, then the body of each arm. There is one pattern per arm
+    Match(Vec<Pattern>),
+    // value, body
+    Let(String),
+    // the statements
+    Block,
+}
+
+struct Node {
+    kind: ExprKind,
+    parent: Option<ExprId>,
+    // Where the node's children are in Arena::links
+    children: Range<usize>,
+}
+
+// A tree kept in two Vecs instead of a Box per node. Dropping it frees the
+// Vecs and whatever text the nodes own, without walking the tree. Nodes are
+// never removed, so an ExprId stays valid for as long as the Arena.
+//
+// The Arena is also an ExprBuilder, so a parser can build into it directly:
+//
+//   let mut parser = Grammar::<Arena>::build_bantam().parser(lexer);
+//   let root = parser.parse_expression();
+//   let arena = parser.into_builder();
+#[derive(Default)]
+pub struct Arena {
+    nodes: Vec<Node>,
+    links: Vec<ExprId>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Every node, including ones that are part of different trees
+    pub fn ids(&self) -> impl Iterator<Item = ExprId> {
+        (0..self.nodes.len() as u32).map(ExprId)
+    }
+
+    pub fn kind(&self, id: ExprId) -> &ExprKind {
+        &self.nodes[id.index()].kind
+    }
+
+    pub fn parent(&self, id: ExprId) -> Option<ExprId> {
+        self.nodes[id.index()].parent
+    }
+
+    pub fn children(&self, id: ExprId) -> &[ExprId] {
+        &self.links[self.nodes[id.index()].children.clone()]
+    }
+
+    pub fn cursor(&self, id: ExprId) -> Cursor<'_> {
+        Cursor { arena: self, id }
+    }
+
+    // Copies the tree under `id` out of the arena, which is also how it gets
+    // printed
+    pub fn to_expr(&self, id: ExprId) -> Expr {
+        let children = self.children(id);
+        let child = |i: usize| Box::new(self.to_expr(children[i]));
+        let rest = |from: usize| children[from..].iter().map(|&c| self.to_expr(c)).collect();
+
+        match self.kind(id).clone() {
+            ExprKind::Name(name) => Expr::Name {
+                name,
+                span: SourceSpan::default(),
+            },
+            ExprKind::Number(value) => Expr::Number {
+                value,
+                span: SourceSpan::default(),
+            },
+            ExprKind::Prefix(operator) => Expr::Prefix {
+                operator,
+                right: child(0),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Binary(operator) => Expr::Binary {
+                left: child(0),
+                operator,
+                right: child(1),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Postfix(operator) => Expr::Postfix {
+                left: child(0),
+                operator,
+                span: SourceSpan::default(),
+            },
+            ExprKind::Conditional => Expr::Conditional {
+                condition: child(0),
+                then_arm: child(1),
+                else_arm: child(2),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Assign(name) => Expr::Assign {
+                name,
+                right: child(0),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Call => Expr::Call {
+                function: child(0),
+                args: rest(1),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Tuple => Expr::Tuple {
+                elements: rest(0),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Lambda(params) => Expr::Lambda {
+                params,
+                body: child(0),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Section {
+                operator,
+                left,
+                right,
+            } => Expr::Section {
+                left: left.then(|| child(0)),
+                operator,
+                right: right.then(|| child(left as usize)),
+                span: SourceSpan::default(),
+            },
+            ExprKind::OptionalMember(name) => Expr::OptionalMember {
+                object: child(0),
+                name,
+                span: SourceSpan::default(),
+            },
+            ExprKind::OptionalCall => Expr::OptionalCall {
+                function: child(0),
+                args: rest(1),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Pipeline => Expr::Pipeline {
+                value: child(0),
+                function: child(1),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Match(patterns) => Expr::Match {
+                value: child(0),
+                arms: patterns.into_iter().zip(rest(1)).collect(),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Let(name) => Expr::Let {
+                name,
+                value: child(0),
+                body: child(1),
+                span: SourceSpan::default(),
+            },
+            ExprKind::Block => Expr::Block {
+                statements: rest(0),
+                span: SourceSpan::default(),
+            },
+        }
+    }
+
+    fn add(&mut self, kind: ExprKind, children: &[ExprId]) -> ExprId {
+        let id = ExprId(self.nodes.len() as u32);
+        self.nodes.push(Node {
+            kind,
+            parent: None,
+            children: 0..0,
+        });
+        self.adopt(id, children);
+        id
+    }
+
+    // Makes `children` the children of `id`, in place of any it had before
+    fn adopt(&mut self, id: ExprId, children: &[ExprId]) {
+        let start = self.links.len();
+        self.links.extend_from_slice(children);
+        for child in children {
+            self.nodes[child.index()].parent = Some(id);
+        }
+        self.nodes[id.index()].children = start..self.links.len();
+    }
+}
+
+// Points at a node and moves around the tree from there. Moving returns a
+// new cursor, or None if there is nothing in that direction
+#[derive(Copy, Clone)]
+pub struct Cursor<'a> {
+    arena: &'a Arena,
+    id: ExprId,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn id(&self) -> ExprId {
+        self.id
+    }
+
+    pub fn kind(&self) -> &'a ExprKind {
+        self.arena.kind(self.id)
+    }
+
+    pub fn parent(&self) -> Option<Cursor<'a>> {
+        self.arena.parent(self.id).map(|id| self.arena.cursor(id))
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = Cursor<'a>> {
+        let arena = self.arena;
+        arena.children(self.id).iter().map(|&id| arena.cursor(id))
+    }
+
+    pub fn first_child(&self) -> Option<Cursor<'a>> {
+        self.child(0)
+    }
+
+    pub fn last_child(&self) -> Option<Cursor<'a>> {
+        let children = self.arena.children(self.id);
+        children.last().map(|&id| self.arena.cursor(id))
+    }
+
+    pub fn child(&self, n: usize) -> Option<Cursor<'a>> {
+        let children = self.arena.children(self.id);
+        children.get(n).map(|&id| self.arena.cursor(id))
+    }
+
+    pub fn next_sibling(&self) -> Option<Cursor<'a>> {
+        let (siblings, i) = self.siblings()?;
+        siblings.get(i + 1).map(|&id| self.arena.cursor(id))
+    }
+
+    pub fn prev_sibling(&self) -> Option<Cursor<'a>> {
+        let (siblings, i) = self.siblings()?;
+        let id = *siblings.get(i.checked_sub(1)?)?;
+        Some(self.arena.cursor(id))
+    }
+
+    // The parent's children and where this node is among them
+    fn siblings(&self) -> Option<(&'a [ExprId], usize)> {
+        let siblings = self.arena.children(self.arena.parent(self.id)?);
+        let i = siblings.iter().position(|&id| id == self.id).unwrap();
+        Some((siblings, i))
+    }
+}
+
+impl ExprBuilder for Arena {
+    type Output = ExprId;
+
+    fn name(&mut self, name: &str) -> ExprId {
+        self.add(ExprKind::Name(name.to_string()), &[])
+    }
+
+    fn number(&mut self, value: &str) -> ExprId {
+        self.add(ExprKind::Number(value.to_string()), &[])
+    }
+
+    fn prefix(&mut self, operator: TokenType, right: ExprId) -> ExprId {
+        self.add(ExprKind::Prefix(operator), &[right])
+    }
+
+    fn binary(&mut self, left: ExprId, operator: TokenType, right: ExprId) -> ExprId {
+        self.add(ExprKind::Binary(operator), &[left, right])
+    }
+
+    fn postfix(&mut self, left: ExprId, operator: TokenType) -> ExprId {
+        self.add(ExprKind::Postfix(operator), &[left])
+    }
+
+    fn conditional(&mut self, condition: ExprId, then_arm: ExprId, else_arm: ExprId) -> ExprId {
+        self.add(ExprKind::Conditional, &[condition, then_arm, else_arm])
+    }
+
+    // The name node turns into the assignment, so it doesn't linger in the
+    // arena without a parent
+    fn assign(&mut self, target: ExprId, right: ExprId) -> ExprId {
+        let name = match self.kind(target) {
+            ExprKind::Name(name) => name.clone(),
+            _ => panic!("left hand side of assignment must be a name"),
+        };
+
+        self.nodes[target.index()].kind = ExprKind::Assign(name);
+        self.adopt(target, &[right]);
+        target
+    }
+
+    fn call(&mut self, function: ExprId, args: Vec<ExprId>) -> ExprId {
+        let mut children = vec![function];
+        children.extend(args);
+        self.add(ExprKind::Call, &children)
+    }
+
+    fn tuple(&mut self, elements: Vec<ExprId>) -> ExprId {
+        self.add(ExprKind::Tuple, &elements)
+    }
+
+    fn lambda(&mut self, params: Vec<String>, body: ExprId) -> ExprId {
+        self.add(ExprKind::Lambda(params), &[body])
+    }
+
+    fn section(
+        &mut self,
+        left: Option<ExprId>,
+        operator: TokenType,
+        right: Option<ExprId>,
+    ) -> ExprId {
+        let kind = ExprKind::Section {
+            operator,
+            left: left.is_some(),
+            right: right.is_some(),
+        };
+        let children: Vec<ExprId> = left.into_iter().chain(right).collect();
+        self.add(kind, &children)
+    }
+
+    fn optional_member(&mut self, object: ExprId, name: &str) -> ExprId {
+        self.add(ExprKind::OptionalMember(name.to_string()), &[object])
+    }
+
+    fn optional_call(&mut self, function: ExprId, args: Vec<ExprId>) -> ExprId {
+        let mut children = vec![function];
+        children.extend(args);
+        self.add(ExprKind::OptionalCall, &children)
+    }
+
+    fn pipeline(&mut self, value: ExprId, function: ExprId) -> ExprId {
+        self.add(ExprKind::Pipeline, &[value, function])
+    }
+
+    fn match_expr(&mut self, value: ExprId, arms: Vec<(Pattern, ExprId)>) -> ExprId {
+        let (patterns, bodies): (Vec<_>, Vec<_>) = arms.into_iter().unzip();
+        let mut children = vec![value];
+        children.extend(bodies);
+        self.add(ExprKind::Match(patterns), &children)
+    }
+
+    // Like assign(), the assignment node is reused for the let
+    fn let_in(&mut self, binding: ExprId, body: ExprId) -> ExprId {
+        let name = match self.kind(binding) {
+            ExprKind::Assign(name) => name.clone(),
+            _ => panic!("let must be followed by an assignment"),
+        };
+
+        let value = self.children(binding)[0];
+        self.nodes[binding.index()].kind = ExprKind::Let(name);
+        self.adopt(binding, &[value, body]);
+        binding
+    }
+
+    fn block(&mut self, statements: Vec<ExprId>) -> ExprId {
+        self.add(ExprKind::Block, &statements)
+    }
+
+    fn is_number(&self, expr: &ExprId) -> bool {
+        matches!(self.kind(*expr), ExprKind::Number(_))
+    }
+}
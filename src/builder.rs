@@ -0,0 +1,391 @@
+use crate::{
+    core::{Span, TokenType},
+    expression::{
+        AssignExpression, BlockExpression, CallExpression, ConditionalExpression, Expr, Expression,
+        LambdaExpression, LetExpression, MatchArm, MatchExpression, NameExpression,
+        NumberExpression, OperatorExpression, OptionalCallExpression, OptionalMemberExpression,
+        PipelineExpression, PostfixExpression, PrefixExpression, SectionExpression, SourceSpan,
+        TupleExpression,
+    },
+    pattern::Pattern,
+};
+
+// The parselets don't build expressions themselves. They hand the pieces they
+// parsed to the parser's builder, which can turn them into whatever it likes:
+// an expression tree, the value of a calculation, bytecode. The builder sees
+// the pieces bottom-up, in the order the parser finishes them
+pub trait ExprBuilder {
+    type Output;
+
+    fn name(&mut self, name: &str) -> Self::Output;
+    fn number(&mut self, value: &str) -> Self::Output;
+    fn prefix(&mut self, operator: TokenType, right: Self::Output) -> Self::Output;
+    fn binary(
+        &mut self,
+        left: Self::Output,
+        operator: TokenType,
+        right: Self::Output,
+    ) -> Self::Output;
+    fn postfix(&mut self, left: Self::Output, operator: TokenType) -> Self::Output;
+
+    // The rest of the language is optional. A builder only has to handle what
+    // its inputs use, anything else is a parse error
+
+    fn conditional(
+        &mut self,
+        _condition: Self::Output,
+        _then_arm: Self::Output,
+        _else_arm: Self::Output,
+    ) -> Self::Output {
+        unsupported("a conditional expression")
+    }
+
+    // The target is whatever was parsed left of `=`, the builder decides what
+    // it can assign to
+    fn assign(&mut self, _target: Self::Output, _right: Self::Output) -> Self::Output {
+        unsupported("an assignment")
+    }
+
+    fn call(&mut self, _function: Self::Output, _args: Vec<Self::Output>) -> Self::Output {
+        unsupported("a call")
+    }
+
+    fn tuple(&mut self, _elements: Vec<Self::Output>) -> Self::Output {
+        unsupported("a tuple")
+    }
+
+    fn lambda(&mut self, _params: Vec<String>, _body: Self::Output) -> Self::Output {
+        unsupported("a lambda")
+    }
+
+    fn section(
+        &mut self,
+        _left: Option<Self::Output>,
+        _operator: TokenType,
+        _right: Option<Self::Output>,
+    ) -> Self::Output {
+        unsupported("an operator section")
+    }
+
+    fn optional_member(&mut self, _object: Self::Output, _name: &str) -> Self::Output {
+        unsupported("an optional member access")
+    }
+
+    fn optional_call(&mut self, _function: Self::Output, _args: Vec<Self::Output>) -> Self::Output {
+        unsupported("an optional call")
+    }
+
+    fn pipeline(&mut self, _value: Self::Output, _function: Self::Output) -> Self::Output {
+        unsupported("a pipeline")
+    }
+
+    // Since match is a keyword
+    fn match_expr(
+        &mut self,
+        _value: Self::Output,
+        _arms: Vec<(Pattern, Self::Output)>,
+    ) -> Self::Output {
+        unsupported("a match")
+    }
+
+    // The binding is whatever was parsed after `let`, which should have been
+    // an assignment
+    fn let_in(&mut self, _binding: Self::Output, _body: Self::Output) -> Self::Output {
+        unsupported("a let expression")
+    }
+
+    fn block(&mut self, _statements: Vec<Self::Output>) -> Self::Output {
+        unsupported("a block")
+    }
+
+    // Called with where in the source an expression was found once the
+    // parser is done with it, parentheses around it included. It can be
+    // called again for the same expression when it turns out to be wrapped in
+    // parentheses. Builders that don't keep track of spans can leave it out
+    fn span(&mut self, expr: Self::Output, _span: Span) -> Self::Output {
+        expr
+    }
+
+    // One rule of the grammar depends on what has been built so far: `(`
+    // right after a number is a juxtaposition, as in `3(a + b)`. A builder
+    // that can't tell keeps the default and the rule doesn't apply
+    fn is_number(&self, _expr: &Self::Output) -> bool {
+        false
+    }
+}
+
+fn unsupported(construct: &str) -> ! {
+    panic!("{construct} is not supported by this builder");
+}
+
+// Builds the Expression tree. This is what a parser uses unless it is given
+// another builder
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TreeBuilder;
+
+impl ExprBuilder for TreeBuilder {
+    type Output = Box<dyn Expression>;
+
+    fn name(&mut self, name: &str) -> Self::Output {
+        Box::new(NameExpression::new(name.to_string()))
+    }
+
+    fn number(&mut self, value: &str) -> Self::Output {
+        Box::new(NumberExpression::new(value.to_string()))
+    }
+
+    fn prefix(&mut self, operator: TokenType, right: Self::Output) -> Self::Output {
+        Box::new(PrefixExpression::new(operator, right))
+    }
+
+    fn binary(
+        &mut self,
+        left: Self::Output,
+        operator: TokenType,
+        right: Self::Output,
+    ) -> Self::Output {
+        Box::new(OperatorExpression::new(left, operator, right))
+    }
+
+    fn postfix(&mut self, left: Self::Output, operator: TokenType) -> Self::Output {
+        Box::new(PostfixExpression::new(left, operator))
+    }
+
+    fn conditional(
+        &mut self,
+        condition: Self::Output,
+        then_arm: Self::Output,
+        else_arm: Self::Output,
+    ) -> Self::Output {
+        Box::new(ConditionalExpression::new(condition, then_arm, else_arm))
+    }
+
+    fn assign(&mut self, target: Self::Output, right: Self::Output) -> Self::Output {
+        let name = match target.as_any().downcast_ref::<NameExpression>() {
+            Some(ne) => ne.name(),
+            None => panic!("left hand side of assignment must be a name"),
+        };
+
+        Box::new(AssignExpression::new(name.clone(), right))
+    }
+
+    fn call(&mut self, function: Self::Output, args: Vec<Self::Output>) -> Self::Output {
+        Box::new(CallExpression::new(function, args))
+    }
+
+    fn tuple(&mut self, elements: Vec<Self::Output>) -> Self::Output {
+        Box::new(TupleExpression::new(elements))
+    }
+
+    fn lambda(&mut self, params: Vec<String>, body: Self::Output) -> Self::Output {
+        Box::new(LambdaExpression::new(params, body))
+    }
+
+    fn section(
+        &mut self,
+        left: Option<Self::Output>,
+        operator: TokenType,
+        right: Option<Self::Output>,
+    ) -> Self::Output {
+        Box::new(SectionExpression::new(left, operator, right))
+    }
+
+    fn optional_member(&mut self, object: Self::Output, name: &str) -> Self::Output {
+        Box::new(OptionalMemberExpression::new(object, name.to_string()))
+    }
+
+    fn optional_call(&mut self, function: Self::Output, args: Vec<Self::Output>) -> Self::Output {
+        Box::new(OptionalCallExpression::new(function, args))
+    }
+
+    fn pipeline(&mut self, value: Self::Output, function: Self::Output) -> Self::Output {
+        Box::new(PipelineExpression::new(value, function))
+    }
+
+    fn match_expr(
+        &mut self,
+        value: Self::Output,
+        arms: Vec<(Pattern, Self::Output)>,
+    ) -> Self::Output {
+        let arms = arms
+            .into_iter()
+            .map(|(pattern, body)| MatchArm::new(pattern, body))
+            .collect();
+        Box::new(MatchExpression::new(value, arms))
+    }
+
+    fn let_in(&mut self, binding: Self::Output, body: Self::Output) -> Self::Output {
+        if !binding.as_any().is::<AssignExpression>() {
+            panic!("let must be followed by an assignment");
+        }
+
+        Box::new(LetExpression::new(binding, body))
+    }
+
+    fn block(&mut self, statements: Vec<Self::Output>) -> Self::Output {
+        Box::new(BlockExpression::new(statements))
+    }
+
+    fn is_number(&self, expr: &Self::Output) -> bool {
+        expr.as_any().is::<NumberExpression>()
+    }
+}
+
+// Builds the Expr enum, for when the tree is going to be matched on
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AstBuilder;
+
+impl ExprBuilder for AstBuilder {
+    type Output = Expr;
+
+    fn name(&mut self, name: &str) -> Expr {
+        Expr::Name {
+            name: name.to_string(),
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn number(&mut self, value: &str) -> Expr {
+        Expr::Number {
+            value: value.to_string(),
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn prefix(&mut self, operator: TokenType, right: Expr) -> Expr {
+        Expr::Prefix {
+            operator,
+            right: Box::new(right),
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn binary(&mut self, left: Expr, operator: TokenType, right: Expr) -> Expr {
+        Expr::Binary {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn postfix(&mut self, left: Expr, operator: TokenType) -> Expr {
+        Expr::Postfix {
+            left: Box::new(left),
+            operator,
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn conditional(&mut self, condition: Expr, then_arm: Expr, else_arm: Expr) -> Expr {
+        Expr::Conditional {
+            condition: Box::new(condition),
+            then_arm: Box::new(then_arm),
+            else_arm: Box::new(else_arm),
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn assign(&mut self, target: Expr, right: Expr) -> Expr {
+        match target {
+            Expr::Name { name, .. } => Expr::Assign {
+                name,
+                right: Box::new(right),
+                span: SourceSpan::default(),
+            },
+            _ => panic!("left hand side of assignment must be a name"),
+        }
+    }
+
+    fn call(&mut self, function: Expr, args: Vec<Expr>) -> Expr {
+        Expr::Call {
+            function: Box::new(function),
+            args,
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn tuple(&mut self, elements: Vec<Expr>) -> Expr {
+        Expr::Tuple {
+            elements,
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn lambda(&mut self, params: Vec<String>, body: Expr) -> Expr {
+        Expr::Lambda {
+            params,
+            body: Box::new(body),
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn section(&mut self, left: Option<Expr>, operator: TokenType, right: Option<Expr>) -> Expr {
+        Expr::Section {
+            left: left.map(Box::new),
+            operator,
+            right: right.map(Box::new),
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn optional_member(&mut self, object: Expr, name: &str) -> Expr {
+        Expr::OptionalMember {
+            object: Box::new(object),
+            name: name.to_string(),
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn optional_call(&mut self, function: Expr, args: Vec<Expr>) -> Expr {
+        Expr::OptionalCall {
+            function: Box::new(function),
+            args,
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn pipeline(&mut self, value: Expr, function: Expr) -> Expr {
+        Expr::Pipeline {
+            value: Box::new(value),
+            function: Box::new(function),
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn match_expr(&mut self, value: Expr, arms: Vec<(Pattern, Expr)>) -> Expr {
+        Expr::Match {
+            value: Box::new(value),
+            arms,
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn let_in(&mut self, binding: Expr, body: Expr) -> Expr {
+        match binding {
+            Expr::Assign { name, right, .. } => Expr::Let {
+                name,
+                value: right,
+                body: Box::new(body),
+                span: SourceSpan::default(),
+            },
+            _ => panic!("let must be followed by an assignment"),
+        }
+    }
+
+    fn block(&mut self, statements: Vec<Expr>) -> Expr {
+        Expr::Block {
+            statements,
+            span: SourceSpan::default(),
+        }
+    }
+
+    fn span(&mut self, mut expr: Expr, span: Span) -> Expr {
+        expr.set_span(span);
+        expr
+    }
+
+    fn is_number(&self, expr: &Expr) -> bool {
+        matches!(expr, Expr::Number { .. })
+    }
+}
@@ -1,12 +1,18 @@
-use std::{collections::HashMap, fmt::Display, rc::Rc};
+use std::{
+    any::Any,
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    io::{self, BufRead, BufReader, ErrorKind, Read},
+    ops::BitOr,
+    sync::{Arc, OnceLock},
+};
 
 use crate::{
-    expression::Expression,
-    parselet::{
-        AssignParselet, BinaryOperatorParselet, CallParselet, ConditionalParselet, GroupParselet,
-        InfixParselet, NameParselet, PostfixOperatorParselet, PrefixOperatorParselet,
-        PrefixParselet,
-    },
+    builder::{ExprBuilder, TreeBuilder},
+    grammar::Grammar,
+    parselet::{Fixity, InfixEntry, InfixParselet, PrefixParselet},
 };
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -23,31 +29,153 @@ pub enum TokenType {
     Tilde,
     Bang,
     Question,
+    QuestionQuestion,
+    QuestionDot,
     Colon,
+    Semicolon,
+    LeftBrace,
+    RightBrace,
+    Arrow,
+    Pipe,
+    DotDot,
+    DotDotEqual,
+    Underscore,
+    Let,
+    In,
+    Match,
+    // An operator declared in the source, like `infixl 6 <+>`
+    Operator(OperatorSymbol),
     Name,
+    Number,
     EOF,
 }
 
 impl TokenType {
-    pub fn punctuator(&self) -> Option<char> {
+    pub fn punctuator(&self) -> Option<&str> {
+        match self {
+            TokenType::Operator(symbol) => Some(symbol.as_str()),
+            _ => self.builtin_punctuator(),
+        }
+    }
+
+    // The punctuators that are the same for every lexer
+    fn builtin_punctuator(&self) -> Option<&'static str> {
         match *self {
-            TokenType::LeftParen => Some('('),
-            TokenType::RightParen => Some(')'),
-            TokenType::Comma => Some(','),
-            TokenType::Assign => Some('='),
-            TokenType::Plus => Some('+'),
-            TokenType::Minus => Some('-'),
-            TokenType::Asterisk => Some('*'),
-            TokenType::Slash => Some('/'),
-            TokenType::Caret => Some('^'),
-            TokenType::Tilde => Some('~'),
-            TokenType::Bang => Some('!'),
-            TokenType::Question => Some('?'),
-            TokenType::Colon => Some(':'),
+            TokenType::LeftParen => Some("("),
+            TokenType::RightParen => Some(")"),
+            TokenType::Comma => Some(","),
+            TokenType::Assign => Some("="),
+            TokenType::Plus => Some("+"),
+            TokenType::Minus => Some("-"),
+            TokenType::Asterisk => Some("*"),
+            TokenType::Slash => Some("/"),
+            TokenType::Caret => Some("^"),
+            TokenType::Tilde => Some("~"),
+            TokenType::Bang => Some("!"),
+            TokenType::Question => Some("?"),
+            TokenType::QuestionQuestion => Some("??"),
+            TokenType::QuestionDot => Some("?."),
+            TokenType::Colon => Some(":"),
+            TokenType::Arrow => Some("->"),
+            TokenType::Pipe => Some("|>"),
+            TokenType::DotDot => Some(".."),
+            TokenType::DotDotEqual => Some("..="),
+            TokenType::Underscore => Some("_"),
+            TokenType::Semicolon => Some(";"),
+            TokenType::LeftBrace => Some("{"),
+            TokenType::RightBrace => Some("}"),
             _ => None,
         }
     }
 
+    pub fn keyword(&self) -> Option<&'static str> {
+        match *self {
+            TokenType::Let => Some("let"),
+            TokenType::In => Some("in"),
+            TokenType::Match => Some("match"),
+            _ => None,
+        }
+    }
+
+    // True for the tokens that can be the operator of a prefix, binary or
+    // postfix expression: the built-in arithmetic ones and declared ones.
+    // Brackets, separators and the punctuators that already mean something
+    // else, like `->` or `?`, can't be declared as operators
+    pub fn is_operator(&self) -> bool {
+        matches!(
+            *self,
+            TokenType::Plus
+                | TokenType::Minus
+                | TokenType::Asterisk
+                | TokenType::Slash
+                | TokenType::Caret
+                | TokenType::Tilde
+                | TokenType::Bang
+                | TokenType::QuestionQuestion
+                | TokenType::DotDot
+                | TokenType::DotDotEqual
+                | TokenType::Operator(_)
+        )
+    }
+
+    // The TokenType of a declared operator, or None if `symbol` isn't made of
+    // operator chars or is longer than OperatorSymbol::MAX_LEN
+    pub fn operator(symbol: &str) -> Option<TokenType> {
+        OperatorSymbol::new(symbol).map(TokenType::Operator)
+    }
+
+    // The reverse of punctuator() for the tokens where is_operator() holds.
+    // Any other run of operator symbols is taken for a declared operator
+    pub fn from_operator(text: &str) -> Option<TokenType> {
+        match TokenType::values()
+            .into_iter()
+            .find(|tt| tt.punctuator() == Some(text))
+        {
+            Some(tt) => Some(tt).filter(TokenType::is_operator),
+            None => TokenType::operator(text),
+        }
+    }
+
+    // The number of token kinds that have an index()
+    pub const COUNT: usize = 29;
+
+    // A dense index for every kind except declared operators, which there can
+    // be any number of. Used to look up parselets in plain arrays
+    pub fn index(&self) -> Option<usize> {
+        match *self {
+            TokenType::LeftParen => Some(0),
+            TokenType::RightParen => Some(1),
+            TokenType::Comma => Some(2),
+            TokenType::Assign => Some(3),
+            TokenType::Plus => Some(4),
+            TokenType::Minus => Some(5),
+            TokenType::Asterisk => Some(6),
+            TokenType::Slash => Some(7),
+            TokenType::Caret => Some(8),
+            TokenType::Tilde => Some(9),
+            TokenType::Bang => Some(10),
+            TokenType::Question => Some(11),
+            TokenType::QuestionQuestion => Some(12),
+            TokenType::QuestionDot => Some(13),
+            TokenType::Colon => Some(14),
+            TokenType::Semicolon => Some(15),
+            TokenType::LeftBrace => Some(16),
+            TokenType::RightBrace => Some(17),
+            TokenType::Arrow => Some(18),
+            TokenType::Pipe => Some(19),
+            TokenType::DotDot => Some(20),
+            TokenType::DotDotEqual => Some(21),
+            TokenType::Underscore => Some(22),
+            TokenType::Let => Some(23),
+            TokenType::In => Some(24),
+            TokenType::Match => Some(25),
+            TokenType::Name => Some(26),
+            TokenType::Number => Some(27),
+            TokenType::EOF => Some(28),
+            TokenType::Operator(_) => None,
+        }
+    }
+
     pub fn values() -> Vec<TokenType> {
         Vec::from([
             TokenType::LeftParen,
@@ -62,8 +190,22 @@ impl TokenType {
             TokenType::Tilde,
             TokenType::Bang,
             TokenType::Question,
+            TokenType::QuestionQuestion,
+            TokenType::QuestionDot,
             TokenType::Colon,
+            TokenType::Semicolon,
+            TokenType::LeftBrace,
+            TokenType::RightBrace,
+            TokenType::Arrow,
+            TokenType::Pipe,
+            TokenType::DotDot,
+            TokenType::DotDotEqual,
+            TokenType::Underscore,
+            TokenType::Let,
+            TokenType::In,
+            TokenType::Match,
             TokenType::Name,
+            TokenType::Number,
             TokenType::EOF,
         ])
     }
@@ -84,34 +226,144 @@ impl Display for TokenType {
             TokenType::Tilde => write!(f, "TILDE"),
             TokenType::Bang => write!(f, "BANG"),
             TokenType::Question => write!(f, "QUESTION"),
+            TokenType::QuestionQuestion => write!(f, "QUESTION_QUESTION"),
+            TokenType::QuestionDot => write!(f, "QUESTION_DOT"),
             TokenType::Colon => write!(f, "COLON"),
+            TokenType::Semicolon => write!(f, "SEMICOLON"),
+            TokenType::LeftBrace => write!(f, "LEFT_BRACE"),
+            TokenType::RightBrace => write!(f, "RIGHT_BRACE"),
+            TokenType::Arrow => write!(f, "ARROW"),
+            TokenType::Pipe => write!(f, "PIPE"),
+            TokenType::DotDot => write!(f, "DOT_DOT"),
+            TokenType::DotDotEqual => write!(f, "DOT_DOT_EQUAL"),
+            TokenType::Underscore => write!(f, "UNDERSCORE"),
+            TokenType::Let => write!(f, "LET"),
+            TokenType::In => write!(f, "IN"),
+            TokenType::Match => write!(f, "MATCH"),
+            TokenType::Operator(_) => write!(f, "OPERATOR"),
             TokenType::Name => write!(f, "NAME"),
+            TokenType::Number => write!(f, "NUMBER"),
             TokenType::EOF => write!(f, "EOF"),
         }
     }
 }
 
+// The symbol of a declared operator. It is kept in the TokenType itself, so
+// TokenType stays Copy and every lexer can declare its own operators without
+// a table that outlives it. Operator chars are ASCII, so any prefix of the
+// bytes is valid UTF-8
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct OperatorSymbol {
+    len: u8,
+    bytes: [u8; OperatorSymbol::MAX_LEN],
+}
+
+impl OperatorSymbol {
+    pub const MAX_LEN: usize = 16;
+
+    fn new(symbol: &str) -> Option<Self> {
+        if symbol.is_empty()
+            || symbol.len() > OperatorSymbol::MAX_LEN
+            || !symbol.chars().all(is_operator_symbol)
+        {
+            return None;
+        }
+
+        let mut bytes = [0; OperatorSymbol::MAX_LEN];
+        bytes[..symbol.len()].copy_from_slice(symbol.as_bytes());
+        Some(Self {
+            len: symbol.len() as u8,
+            bytes,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap()
+    }
+}
+
+impl std::fmt::Debug for OperatorSymbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+// Where a token is in the source, as byte offsets
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+// The text is usually borrowed, either from the source or, for punctuators
+// and keywords, from the TokenType.
+//
+// Tokens don't have to come from Lexer. Another tokenizer can make them with
+// with_span() and hang whatever else it knows about a token, like a parsed
+// literal value, on it as a payload for its parselets to pick up
 #[derive(Debug, Clone)]
-pub struct Token {
+pub struct Token<'src> {
     token_type: TokenType,
-    pub text: String,
+    pub text: Cow<'src, str>,
+    span: Span,
+    payload: Option<Arc<dyn Any + Send + Sync>>,
 }
 
-impl Token {
-    pub fn new(token_type: TokenType, text: String) -> Self {
-        Self { token_type, text }
+impl<'src> Token<'src> {
+    pub fn new(token_type: TokenType, text: impl Into<Cow<'src, str>>) -> Self {
+        Self::with_span(token_type, text, Span::default())
+    }
+
+    pub fn with_span(token_type: TokenType, text: impl Into<Cow<'src, str>>, span: Span) -> Self {
+        Self {
+            token_type,
+            text: text.into(),
+            span,
+            payload: None,
+        }
+    }
+
+    pub fn with_payload(mut self, payload: impl Any + Send + Sync) -> Self {
+        self.payload = Some(Arc::new(payload));
+        self
     }
 
     pub fn get_type(&self) -> &TokenType {
         &self.token_type
     }
 
-    pub fn get_text(&self) -> &String {
+    pub fn get_text(&self) -> &str {
         &self.text
     }
+
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
+
+    // Returns the payload if there is one of type P
+    pub fn get_payload<P: Any>(&self) -> Option<&P> {
+        self.payload.as_deref()?.downcast_ref()
+    }
+
+    // Copies the text if it is borrowed from the source
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            token_type: self.token_type,
+            text: Cow::Owned(self.text.into_owned()),
+            span: self.span,
+            payload: self.payload,
+        }
+    }
 }
 
-impl Display for Token {
+impl Display for Token<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} \"{}\"", self.token_type, self.text)
     }
@@ -121,17 +373,32 @@ impl Display for Token {
 // determine how a series of infix expressions will be grouped. For example,
 // "a + b * c - d" will be parsed as "(a + (b * c)) - d" because "*" has higher
 // precedence than "+" and "-". Here, bigger numbers mean higher precedence.
+//
+// Under the hood the parser compares binding powers rather than precedence
+// levels. Every level covers two of them so that an operator can bind a
+// little tighter on one side than on the other, which is how associativity is
+// expressed, and so that custom operators can sit between two levels.
 #[derive(PartialEq, PartialOrd, Clone, Debug, Copy)]
 pub enum Precedence {
     Everything = 0,
     Assignment = 1,
     Conditional = 2,
-    Sum = 3,
-    Product = 4,
-    Exponent = 5,
-    Prefix = 6,
-    Postfix = 7,
-    Call = 8,
+    Coalesce = 3,
+    Pipeline = 4,
+    Range = 5,
+    Sum = 6,
+    Product = 7,
+    Exponent = 8,
+    Prefix = 9,
+    Postfix = 10,
+    Call = 11,
+}
+
+impl Precedence {
+    // The lower of the two binding powers covered by this level
+    pub fn binding_power(self) -> u8 {
+        self as u8 * 2
+    }
 }
 
 impl From<usize> for Precedence {
@@ -140,147 +407,744 @@ impl From<usize> for Precedence {
             0 => Self::Everything,
             1 => Self::Assignment,
             2 => Self::Conditional,
-            3 => Self::Sum,
-            4 => Self::Product,
-            5 => Self::Exponent,
-            6 => Self::Prefix,
-            7 => Self::Postfix,
-            8 => Self::Call,
+            3 => Self::Coalesce,
+            4 => Self::Pipeline,
+            5 => Self::Range,
+            6 => Self::Sum,
+            7 => Self::Product,
+            8 => Self::Exponent,
+            9 => Self::Prefix,
+            10 => Self::Postfix,
+            11 => Self::Call,
             _ => panic!("Invalid precedence value"),
         }
     }
 }
 
 // A very primitive lexer. Takes a string and splits it into a series of
-// Tokens. Operators and punctuation are mapped to unique keywords. Names,
-// which can be any series of letters, are turned into NAME tokens. All other
-// characters are ignored (except to separate names). Numbers and strings are
-// not supported. This is really just the bare minimum to give the parser
+// Tokens. Operators and punctuation are mapped to unique keywords, always
+// taking the longest punctuator that matches (so "->" is never read as "-"
+// followed by something else). That is also how "?", "??" and "?." are told
+// apart. An optional call `a?(b)` is lexed as "?" and "(" like the conditional
+// `a ?(b) : c`, the parser tells them apart.
+// Names, which can be any series of letters, are turned into NAME tokens
+// unless they spell one of the reserved keywords. Runs of digits become NUMBER
+// tokens. All other characters are ignored (except to separate names). Strings
+// are not supported. This is really just the bare minimum to give the parser
 // something to work with.
+//
+// The one bit of state is for operator declarations. In `infixl 6 <+>` the
+// symbol after the precedence is registered as a new punctuator, so every
+// `<+>` after that point is lexed as a single OPERATOR token. A declaration
+// is a NAME spelling one of DECLARATION_KEYWORDS followed by a NUMBER, at the
+// start of the input, after `;` or after another declaration. Those are the
+// places BantamParser looks for them, everywhere else `infixl` is just a name.
+//
+// The lexer walks the source in place. The text of names and numbers is
+// borrowed from it and everything else is borrowed from the TokenType, so
+// lexing doesn't allocate. Use into_owned() for tokens that have to outlive
+// the source.
 #[derive(Debug, Clone)]
-pub struct Lexer {
+pub struct Lexer<'src> {
+    source: &'src str,
+    // Byte offset of the next char to read
     index: usize,
-    text: Vec<char>,
-    punctuators: HashMap<char, TokenType>,
+    rules: LexerRules,
+    finished: bool,
+}
+
+// How far into an operator declaration the lexer is
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Declaration {
+    // Nowhere a declaration can start
+    None,
+    // Where a declaration can start
+    Start,
+    Keyword,
+    Precedence,
+}
+
+// The punctuators and keywords of the built-in TokenTypes. They are the same
+// for every lexer, so they are only collected once
+struct LexerTables {
+    punctuators: Vec<TokenType>,
+    keywords: HashMap<&'static str, TokenType>,
 }
 
-impl Lexer {
-    pub fn new(text_input: String) -> Self {
-        let mut punctuators: HashMap<char, TokenType> = HashMap::new();
+fn lexer_tables() -> &'static LexerTables {
+    static TABLES: OnceLock<LexerTables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut punctuators: Vec<TokenType> = Vec::new();
+        let mut keywords: HashMap<&'static str, TokenType> = HashMap::new();
 
-        // Register TokenTypes that are explicit punctuators
+        // Register TokenTypes that are explicit punctuators or keywords
         for tt in TokenType::values() {
-            if let Some(x) = tt.punctuator() {
-                punctuators.insert(x, tt);
+            if tt.punctuator().is_some() {
+                punctuators.push(tt);
+            }
+            if let Some(k) = tt.keyword() {
+                keywords.insert(k, tt);
+            }
+        }
+
+        sort_punctuators(&mut punctuators);
+
+        LexerTables {
+            punctuators,
+            keywords,
+        }
+    })
+}
+
+// Everything the lexers need besides the text itself: which punctuators and
+// keywords there are and how far into an operator declaration we are. Shared
+// by Lexer, which has all of the input, and StreamLexer, which only has the
+// part it has read so far
+#[derive(Debug, Clone)]
+struct LexerRules {
+    // Sorted longest first so multi-char punctuators win over their prefixes.
+    // Only copied once an operator is declared
+    punctuators: Cow<'static, [TokenType]>,
+    keywords: &'static HashMap<&'static str, TokenType>,
+    declaration: Declaration,
+}
+
+impl LexerRules {
+    fn new() -> Self {
+        let tables = lexer_tables();
+
+        Self {
+            punctuators: Cow::Borrowed(&tables.punctuators),
+            keywords: &tables.keywords,
+            declaration: Declaration::Start,
+        }
+    }
+
+    // Lexes the token at `index` in `source`. If `source` is only the start
+    // of the input, a token that runs into its end might go on in the next
+    // part. Then this returns None without changing anything but `index`,
+    // which is moved past any whitespace, and should be called again with
+    // more text
+    fn next_token<'a>(
+        &mut self,
+        source: &'a str,
+        index: &mut usize,
+        complete: bool,
+    ) -> Option<Token<'a>> {
+        let token = self.read_token(source, index, complete)?;
+
+        self.declaration = match (self.declaration, token.get_type()) {
+            (Declaration::Start, TokenType::Name) if is_declaration_keyword(token.get_text()) => {
+                Declaration::Keyword
+            }
+            (Declaration::Keyword, TokenType::Number) => Declaration::Precedence,
+            // After the declared operator another declaration can follow
+            (Declaration::Precedence, _) | (_, TokenType::Semicolon) => Declaration::Start,
+            _ => Declaration::None,
+        };
+
+        Some(token)
+    }
+
+    fn read_token<'a>(
+        &mut self,
+        source: &'a str,
+        index: &mut usize,
+        complete: bool,
+    ) -> Option<Token<'a>> {
+        while let Some(c) = source[*index..].chars().next() {
+            let start = *index;
+            if self.declaration == Declaration::Precedence && is_operator_symbol(c) {
+                let symbol = read_while(source, index, is_operator_symbol);
+                if !complete && *index == source.len() {
+                    *index = start;
+                    return None;
+                }
+
+                let tt = self.declare_operator(symbol);
+                return Some(Token::with_span(tt, symbol, Span::new(start, *index)));
+            }
+
+            // What is left could be the start of a longer punctuator, like
+            // `?` of `??` or `<` of a declared `<+>`
+            let rest = &source[start..];
+            if !complete
+                && self
+                    .punctuators
+                    .iter()
+                    .filter_map(TokenType::punctuator)
+                    .any(|p| p.len() > rest.len() && p.starts_with(rest))
+            {
+                return None;
+            }
+
+            let token = if let Some(tt) = self.match_punctuator(rest) {
+                let text = &rest[..tt.punctuator().unwrap().len()];
+                *index += text.len();
+                Token::with_span(tt, text, Span::new(start, *index))
+            } else if c.is_alphabetic() {
+                let name = read_while(source, index, char::is_alphabetic);
+                let span = Span::new(start, *index);
+                match self.keywords.get(name).copied() {
+                    Some(tt) => Token::with_span(tt, tt.keyword().unwrap(), span),
+                    None => Token::with_span(TokenType::Name, name, span),
+                }
+            } else if c.is_ascii_digit() {
+                let number = read_while(source, index, |c| c.is_ascii_digit());
+                Token::with_span(TokenType::Number, number, Span::new(start, *index))
+            } else {
+                // Ignore all other chars (whitespace etc.)
+                *index += c.len_utf8();
+                continue;
+            };
+
+            // A name like `ab` could still turn into `abc`
+            if !complete && *index == source.len() {
+                *index = start;
+                return None;
             }
+
+            return Some(token);
+        }
+
+        if !complete {
+            return None;
         }
 
-        println!("{}", text_input);
+        // The input always ends with an EOF token, after that the lexers
+        // return None
+        Some(Token::with_span(
+            TokenType::EOF,
+            "",
+            Span::new(*index, *index),
+        ))
+    }
+
+    // Returns the longest punctuator at the start of `rest`
+    fn match_punctuator(&self, rest: &str) -> Option<TokenType> {
+        self.punctuators
+            .iter()
+            .find(|tt| rest.starts_with(tt.punctuator().unwrap()))
+            .copied()
+    }
+
+    // Registers the symbol of an operator declaration as a new punctuator.
+    // Declaring an existing punctuator just gives it back
+    fn declare_operator(&mut self, symbol: &str) -> TokenType {
+        if let Some(tt) = self
+            .punctuators
+            .iter()
+            .find(|tt| tt.punctuator() == Some(symbol))
+        {
+            return *tt;
+        }
+
+        let tt = TokenType::operator(symbol).unwrap_or_else(|| {
+            panic!(
+                "Could not declare {symbol}, an operator can be at most {} chars",
+                OperatorSymbol::MAX_LEN
+            )
+        });
+        let punctuators = self.punctuators.to_mut();
+        punctuators.push(tt);
+        sort_punctuators(punctuators);
+        tt
+    }
+}
+
+fn sort_punctuators(punctuators: &mut [TokenType]) {
+    punctuators.sort_by_key(|tt| Reverse(tt.punctuator().unwrap().len()));
+}
+
+// Advances `index` past every char that satisfies the predicate and returns
+// the text it went over
+fn read_while<'a>(source: &'a str, index: &mut usize, predicate: fn(char) -> bool) -> &'a str {
+    let rest = &source[*index..];
+    let len = rest.find(|c| !predicate(c)).unwrap_or(rest.len());
+    *index += len;
+    &rest[..len]
+}
 
+// The words that start an operator declaration. They are only keywords there,
+// anywhere else they are ordinary names
+const DECLARATION_KEYWORDS: [&str; 4] = ["infixl", "infixr", "prefix", "postfix"];
+
+fn is_declaration_keyword(name: &str) -> bool {
+    DECLARATION_KEYWORDS.contains(&name)
+}
+
+// Chars that can make up a declared operator. Brackets and separators are left
+// out so a declaration can sit right next to them
+fn is_operator_symbol(c: char) -> bool {
+    c.is_ascii_punctuation() && !"(){},;".contains(c)
+}
+
+impl<'src> Lexer<'src> {
+    pub fn new(source: &'src str) -> Self {
         Self {
+            source,
             index: 0,
-            text: text_input.chars().collect(),
-            punctuators,
+            rules: LexerRules::new(),
+            finished: false,
         }
     }
 
     pub fn has_next(&self) -> bool {
-        self.index < self.text.len()
+        self.index < self.source.len()
+    }
+
+    // Turns every token into one that owns its text, for when the tokens have
+    // to outlive the source
+    pub fn into_owned(self) -> impl Iterator<Item = Token<'static>> + Send + 'src {
+        self.map(Token::into_owned)
     }
 }
 
-impl Iterator for Lexer {
-    type Item = Token;
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Token<'src>;
     fn next(&mut self) -> Option<Self::Item> {
-        while self.index < self.text.len() {
-            let c = self.text.get(self.index).unwrap();
-            self.index += 1;
-
-            if self.punctuators.contains_key(&c) {
-                return Some(Token::new(
-                    *self.punctuators.get(c).unwrap(),
-                    String::from(*c),
+        if self.finished {
+            return None;
+        }
+
+        let token = self.rules.next_token(self.source, &mut self.index, true)?;
+        self.finished = *token.get_type() == TokenType::EOF;
+        Some(token)
+    }
+}
+
+// A lexer that reads its input bit by bit, for inputs too big to keep in
+// memory. It only holds on to the text of the token it is working on, so
+// memory use depends on the longest token rather than on the size of the
+// input. Chars split between two reads are put back together before lexing.
+// Tokens own their text, since the text they were lexed from is dropped
+// again. Spans are still byte offsets from the start of the whole input.
+pub struct StreamLexer<R> {
+    reader: R,
+    // Text that has been read but not lexed yet, starting at byte `offset` of
+    // the input
+    buffer: String,
+    offset: usize,
+    index: usize,
+    // The start of a char that was cut off at the end of the last read
+    partial: Vec<u8>,
+    // Set once the reader has nothing more and once EOF has been returned
+    done: bool,
+    finished: bool,
+    error: Option<io::Error>,
+    rules: LexerRules,
+}
+
+impl<R: BufRead> StreamLexer<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: String::new(),
+            offset: 0,
+            index: 0,
+            partial: Vec::new(),
+            done: false,
+            finished: false,
+            error: None,
+            rules: LexerRules::new(),
+        }
+    }
+
+    // The error that ended the input early, if there was one. Reading stops
+    // at the first error that isn't an interruption and the tokens end with
+    // EOF as if the input ended there, so check this once they run out
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    // Drops the text that has been lexed and appends the next chunk of input
+    fn fill(&mut self) {
+        self.buffer.drain(..self.index);
+        self.offset += self.index;
+        self.index = 0;
+
+        let chunk = loop {
+            match self.reader.fill_buf() {
+                Ok(chunk) => break chunk,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return self.fail(e),
+            }
+        };
+        if chunk.is_empty() {
+            if !self.partial.is_empty() {
+                return self.fail(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Input ends in the middle of a UTF-8 sequence",
                 ));
-            } else if c.is_alphabetic() {
-                let start = self.index - 1;
-                while self.index < self.text.len() {
-                    if !self.text.get(self.index).unwrap().is_alphabetic() {
-                        break;
-                    }
-                    self.index += 1;
-                }
+            }
+            self.done = true;
+            return;
+        }
 
-                let name: String = self.text[start..self.index].iter().collect();
-                return Some(Token::new(TokenType::Name, name));
-            } else {
-                // Ignore all other chars (whitespace etc.)
-                continue;
+        self.partial.extend_from_slice(chunk);
+        let len = chunk.len();
+        self.reader.consume(len);
+
+        let (valid, invalid) = match std::str::from_utf8(&self.partial) {
+            Ok(text) => (text.len(), false),
+            // The last char isn't complete yet, the rest of it is in the next chunk
+            Err(e) if e.error_len().is_none() => (e.valid_up_to(), false),
+            Err(e) => (e.valid_up_to(), true),
+        };
+
+        let text = std::str::from_utf8(&self.partial[..valid]).unwrap();
+        self.buffer.push_str(text);
+        self.partial.drain(..valid);
+
+        if invalid {
+            let at = self.offset + self.buffer.len();
+            self.fail(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid UTF-8 at byte {at}"),
+            ));
+        }
+    }
+
+    // Stops reading, what is in the buffer still gets lexed
+    fn fail(&mut self, error: io::Error) {
+        self.error = Some(error);
+        self.partial.clear();
+        self.done = true;
+    }
+}
+
+impl<R: Read> StreamLexer<BufReader<R>> {
+    pub fn from_reader(reader: R) -> Self {
+        Self::new(BufReader::new(reader))
+    }
+}
+
+impl<R: BufRead> Iterator for StreamLexer<R> {
+    type Item = Token<'static>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        loop {
+            let token = self
+                .rules
+                .next_token(&self.buffer, &mut self.index, self.done);
+            if let Some(token) = token {
+                let tt = *token.get_type();
+                self.finished = tt == TokenType::EOF;
+                let span = token.get_span();
+                let span = Span::new(self.offset + span.start, self.offset + span.end);
+                // Only names, numbers and declared operators point into the buffer
+                let text = match tt.builtin_punctuator().or(tt.keyword()) {
+                    Some(text) => Cow::Borrowed(text),
+                    None => Cow::Owned(token.text.into_owned()),
+                };
+                return Some(Token::with_span(tt, text, span));
             }
+
+            self.fill();
         }
+    }
+}
+
+// Things a parselet can forbid in everything that is parsed inside it, the
+// way Rust doesn't allow a struct literal in the head of an `if`. Parselets
+// push a context with the restrictions they need on the Parser and the
+// parselets for the restricted constructs check it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Restrictions(u8);
+
+impl Restrictions {
+    pub const NONE: Restrictions = Restrictions(0);
+    // `a = b`
+    pub const NO_ASSIGNMENT: Restrictions = Restrictions(1);
+    // `{ a; b }`, which would otherwise be taken for the body of a match
+    pub const NO_BLOCK: Restrictions = Restrictions(1 << 1);
+
+    pub fn contains(self, other: Restrictions) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Restrictions {
+    type Output = Restrictions;
 
-        // Once we've reached the end of the string, just return EOF tokens. We'll
-        // just keeping returning them as many times as we're asked so that the
-        // parser's lookahead doesn't have to worry about running out of tokens.
-        Some(Token::new(TokenType::EOF, String::new()))
+    fn bitor(self, rhs: Restrictions) -> Restrictions {
+        Restrictions(self.0 | rhs.0)
     }
 }
 
-pub struct Parser {
-    tokens: Box<dyn Iterator<Item = Token>>,
-    read: Vec<Token>,
+// An operator that parse_expression_iterative() has read but whose (right)
+// operand it is still parsing, along with the binding power to go back to
+// once the operand is done
+enum Pending<'src, T> {
+    Prefix {
+        token: Token<'src>,
+        binding_power: u8,
+    },
+    Infix {
+        left: T,
+        start: usize,
+        token: Token<'src>,
+        binding_power: u8,
+    },
+}
 
-    // We have separate tables for prefix and infix expressions because sometimes we have both a prefix and infix parselet for the same TokenType. For example, the prefix parselet for `(` handles grouping in an expression like `a * (b + c)`. Meanwhile the infix parselet for `(` handles function calls like `a(b)`
-    prefix_parselets: HashMap<TokenType, Rc<dyn PrefixParselet>>,
-    infix_parselets: HashMap<TokenType, Rc<dyn InfixParselet>>,
+// A saved position in the token stream, see Parser::mark
+pub struct Mark {
+    position: usize,
 }
 
-impl Parser {
-    pub fn new(tokens: Box<dyn Iterator<Item = Token>>) -> Self {
+pub struct Parser<'src, B: ExprBuilder = TreeBuilder> {
+    // Shared with every other parser for the same grammar until this one
+    // registers a parselet of its own
+    grammar: Grammar<B>,
+    // Makes the output out of what the parselets parse
+    builder: B,
+
+    // Any iterator of tokens will do. Once it runs out, the parser acts as if
+    // it ended with an EOF token
+    tokens: Box<dyn Iterator<Item = Token<'src>> + Send + 'src>,
+    read: VecDeque<Token<'src>>,
+    // Where the last token read ended, which is where a made up EOF goes
+    end: usize,
+    // Where the last token consumed ended, which is where the expression
+    // being parsed ends
+    consumed: usize,
+
+    // Index in `read` of the next token to consume. Consumed tokens are only
+    // kept around while there are outstanding marks that could rewind to them
+    position: usize,
+    marks: usize,
+
+    // The innermost context decides what is restricted. The description is
+    // used in error messages, as in "assignment is not allowed in <description>"
+    contexts: Vec<(Restrictions, &'static str)>,
+}
+
+impl<'src> Parser<'src> {
+    pub fn new(tokens: impl IntoIterator<Item = Token<'src>, IntoIter: Send + 'src>) -> Self {
+        Self::with_grammar(Grammar::new(), tokens)
+    }
+}
+
+impl<'src, B: ExprBuilder> Parser<'src, B> {
+    pub fn with_grammar(
+        grammar: Grammar<B>,
+        tokens: impl IntoIterator<Item = Token<'src>, IntoIter: Send + 'src>,
+    ) -> Self
+    where
+        B: Default,
+    {
+        Self::with_builder(grammar, B::default(), tokens)
+    }
+
+    pub fn with_builder(
+        grammar: Grammar<B>,
+        builder: B,
+        tokens: impl IntoIterator<Item = Token<'src>, IntoIter: Send + 'src>,
+    ) -> Self {
         Self {
-            tokens,
-            read: Vec::new(),
-            prefix_parselets: HashMap::new(),
-            infix_parselets: HashMap::new(),
+            grammar,
+            builder,
+            tokens: Box::new(tokens.into_iter()),
+            read: VecDeque::new(),
+            end: 0,
+            consumed: 0,
+            position: 0,
+            marks: 0,
+            contexts: Vec::new(),
         }
     }
 
-    pub fn register_prefix(&mut self, tt: TokenType, parselet: Box<dyn PrefixParselet>) -> () {
-        self.prefix_parselets.insert(tt, Rc::from(parselet));
+    pub fn grammar(&self) -> &Grammar<B> {
+        &self.grammar
+    }
+
+    // Changes made through here only affect this parser
+    pub fn grammar_mut(&mut self) -> &mut Grammar<B> {
+        &mut self.grammar
     }
 
-    pub fn register_infix(&mut self, tt: TokenType, parselet: Box<dyn InfixParselet>) -> () {
-        self.infix_parselets.insert(tt, Rc::from(parselet));
+    // Parselets hand what they parsed to the builder through here
+    pub fn builder(&mut self) -> &mut B {
+        &mut self.builder
     }
 
-    pub fn parse_expression_precedence(&mut self, precedence: Precedence) -> Box<dyn Expression> {
-        let mut token: Token = self.consume();
-        println!("{}", token);
+    // Gives the builder back once parsing is done, for builders that collect
+    // their output on the side
+    pub fn into_builder(self) -> B {
+        self.builder
+    }
+
+    pub fn register_prefix(&mut self, tt: TokenType, parselet: Box<dyn PrefixParselet<B>>) {
+        self.grammar.register_prefix(tt, parselet);
+    }
+
+    pub fn register_infix(&mut self, tt: TokenType, parselet: Box<dyn InfixParselet<B>>) {
+        self.grammar.register_infix(tt, parselet);
+    }
+
+    pub fn register_juxtaposition(&mut self, parselet: Box<dyn InfixParselet<B>>) {
+        self.grammar.register_juxtaposition(parselet);
+    }
+
+    // Parses an expression made of operators above the given precedence level
+    pub fn parse_expression_precedence(&mut self, precedence: Precedence) -> B::Output {
+        self.parse_expression_binding_power(precedence.binding_power() + 1)
+    }
+
+    // Parses an expression, stopping at the first infix parselet whose left
+    // binding power is not higher than `binding_power`
+    pub fn parse_expression_binding_power(&mut self, binding_power: u8) -> B::Output {
+        let mut token = self.consume();
+        let start = token.get_span().start;
         let prefix = self
-            .prefix_parselets
-            .get(token.get_type())
-            .expect(&format!("Could not parse {}.", token.get_text()))
+            .grammar
+            .get_prefix(*token.get_type())
+            .unwrap_or_else(|| panic!("Could not parse {}.", token.get_text()))
             .clone();
 
         let mut left = prefix.parse(self, token);
+        left = self.spanned(left, start);
 
         // if parse_expression() encounters an expression whose precedence is lower than we allow, it stops parsing and returns what it has so far
-        while precedence < self.get_precedence() {
+        loop {
+            if let Some(juxtaposition) = self.get_juxtaposition(&left) {
+                if juxtaposition.left_binding_power() <= binding_power {
+                    break;
+                }
+
+                token = self.peek(0).clone();
+                left = juxtaposition.parse(self, left, token);
+                left = self.spanned(left, start);
+                continue;
+            }
+
+            if self.get_binding_power() <= binding_power {
+                break;
+            }
+
             token = self.consume();
-            let infix = self.infix_parselets.get(token.get_type()).unwrap().clone();
+            let infix = self.grammar.get_infix(*token.get_type()).unwrap().clone();
             left = infix.parse(self, left, token);
+            left = self.spanned(left, start);
         }
 
         left
     }
 
-    pub fn parse_expression(&mut self) -> Box<dyn Expression> {
-        self.parse_expression_precedence(Precedence::Everything)
+    pub fn parse_expression(&mut self) -> B::Output {
+        self.parse_expression_binding_power(0)
+    }
+
+    // Does the same as parse_expression() but keeps the operators that are
+    // waiting for an operand on a stack on the heap instead of recursing, so
+    // operators can be nested as deeply as memory allows. Parselets with a
+    // Fixity are handled here, all others are still called and may recurse.
+    // The TreeBuilder's nodes drop without recursing too, but print(), clone()
+    // and == still recurse once per level, and so does dropping an Expr from
+    // the AstBuilder. Use the Arena to do more with very deep trees
+    pub fn parse_expression_iterative(&mut self) -> B::Output {
+        let mut pending: Vec<Pending<'src, B::Output>> = Vec::new();
+        let mut binding_power = 0;
+        let mut start;
+
+        'operand: loop {
+            let token = self.consume();
+            start = token.get_span().start;
+            let prefix = self
+                .grammar
+                .get_prefix(*token.get_type())
+                .unwrap_or_else(|| panic!("Could not parse {}.", token.get_text()))
+                .clone();
+
+            let mut left = match prefix.fixity() {
+                Some(Fixity::Prefix {
+                    right_binding_power,
+                }) => {
+                    pending.push(Pending::Prefix {
+                        token,
+                        binding_power,
+                    });
+                    binding_power = right_binding_power;
+                    continue 'operand;
+                }
+                _ => prefix.parse(self, token),
+            };
+            left = self.spanned(left, start);
+
+            // The same loop as in parse_expression_binding_power(), except
+            // that when it would return, the operator waiting for `left` is
+            // taken off the stack and the loop goes on one level up
+            loop {
+                let done = if let Some(juxtaposition) = self.get_juxtaposition(&left) {
+                    if juxtaposition.left_binding_power() > binding_power {
+                        let token = self.peek(0).clone();
+                        left = juxtaposition.parse(self, left, token);
+                        left = self.spanned(left, start);
+                        continue;
+                    }
+                    true
+                } else {
+                    self.get_binding_power() <= binding_power
+                };
+
+                if !done {
+                    let token = self.consume();
+                    let infix = self.grammar.get_infix(*token.get_type()).unwrap().clone();
+                    match infix.fixity() {
+                        Some(Fixity::Infix {
+                            right_binding_power,
+                        }) => {
+                            pending.push(Pending::Infix {
+                                left,
+                                start,
+                                token,
+                                binding_power,
+                            });
+                            binding_power = right_binding_power;
+                            continue 'operand;
+                        }
+                        Some(Fixity::Postfix) => {
+                            left = self.builder.postfix(left, *token.get_type());
+                        }
+                        _ => left = infix.parse(self, left, token),
+                    }
+                    left = self.spanned(left, start);
+                    continue;
+                }
+
+                match pending.pop() {
+                    None => return left,
+                    Some(Pending::Prefix {
+                        token,
+                        binding_power: outer,
+                    }) => {
+                        left = self.builder.prefix(*token.get_type(), left);
+                        start = token.get_span().start;
+                        binding_power = outer;
+                    }
+                    Some(Pending::Infix {
+                        left: operand,
+                        start: operand_start,
+                        token,
+                        binding_power: outer,
+                    }) => {
+                        left = self.builder.binary(operand, *token.get_type(), left);
+                        start = operand_start;
+                        binding_power = outer;
+                    }
+                }
+                left = self.spanned(left, start);
+            }
+        }
     }
 
     // Since match is a keyword
     pub fn match_tok(&mut self, expected: TokenType) -> bool {
-        let token = self.look_ahead(0);
-        if *token.get_type() != expected {
+        if *self.peek(0).get_type() != expected {
             // panic!("Expected {} and found {}", expected, token.get_type());
             false
         } else {
@@ -289,103 +1153,370 @@ impl Parser {
         }
     }
 
-    pub fn consume_expected(&mut self, expected: TokenType) -> Token {
-        let tok = self.look_ahead(0);
-        if *tok.get_type() != expected {
-            panic!("Expect token {} and found {}", expected, tok.get_type());
+    pub fn consume_expected(&mut self, expected: TokenType) -> Token<'src> {
+        let tok_type = *self.peek(0).get_type();
+        if tok_type != expected {
+            panic!("Expect token {} and found {}", expected, tok_type);
         }
 
         self.consume()
     }
 
-    pub fn consume(&mut self) -> Token {
-        self.look_ahead(0);
-        self.read.remove(0)
+    pub fn consume(&mut self) -> Token<'src> {
+        self.consumed = self.peek(0).get_span().end;
+        if self.marks > 0 {
+            self.position += 1;
+            return self.read[self.position - 1].clone();
+        }
+
+        self.read.pop_front().unwrap()
+    }
+
+    // Tells the builder where the expression that began at `start` and ends
+    // with the last token consumed is in the source
+    fn spanned(&mut self, expr: B::Output, start: usize) -> B::Output {
+        let span = Span::new(start, self.consumed);
+        self.builder.span(expr, span)
+    }
+
+    // Saves the current position so parselets can try one reading of the
+    // input and fall back to another. Every mark has to be handed back to
+    // either rewind() or release(). Changes to the parselet tables made in the
+    // meantime are not undone
+    pub fn mark(&mut self) -> Mark {
+        self.marks += 1;
+        Mark {
+            position: self.position,
+        }
+    }
+
+    // Goes back to a marked position, so the tokens consumed since are read again
+    pub fn rewind(&mut self, mark: Mark) {
+        self.position = mark.position;
+        self.release(mark);
+    }
+
+    // Keeps everything consumed since the mark
+    pub fn release(&mut self, _mark: Mark) {
+        self.marks -= 1;
+        if self.marks == 0 {
+            self.read.drain(..self.position);
+            self.position = 0;
+        }
+    }
+
+    // Runs `parse` and rewinds to where it started if it returns None
+    pub fn try_parse<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Parser<'src, B>) -> Option<T>,
+    ) -> Option<T> {
+        let mark = self.mark();
+        let result = parse(self);
+        match result {
+            Some(_) => self.release(mark),
+            None => self.rewind(mark),
+        }
+
+        result
+    }
+
+    // True if `tt` starts a prefix expression in this grammar
+    pub(crate) fn has_prefix(&self, tt: TokenType) -> bool {
+        self.grammar.get_prefix(tt).is_some()
+    }
+
+    // True if `tt` is registered as a binary operator, which is what can be
+    // turned into a section like `(+)`, `(* 2)` or `(2 *)`
+    pub(crate) fn is_binary_operator(&self, tt: TokenType) -> bool {
+        self.grammar
+            .get_infix(tt)
+            .is_some_and(|infix| infix.is_binary_operator())
+    }
+
+    // True if the `(` `distance` tokens ahead, right after a `?`, opens the
+    // arguments of an optional call `a?(b)` rather than the then arm of a
+    // conditional `a ?(b) : c`. It's a conditional if a `:` for the `?`
+    // follows before the end of the expression. Nested conditionals bring
+    // their own `:`, and brackets are skipped as a whole
+    pub(crate) fn is_optional_call(&mut self, distance: usize) -> bool {
+        if *self.peek(distance).get_type() != TokenType::LeftParen {
+            return false;
+        }
+
+        let mut depth = 0;
+        let mut conditionals = 0;
+        let mut distance = distance;
+        loop {
+            match self.peek(distance).get_type() {
+                TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBrace if depth == 0 => return true,
+                TokenType::RightParen | TokenType::RightBrace => depth -= 1,
+                TokenType::Question if depth == 0 => conditionals += 1,
+                TokenType::Colon if depth == 0 && conditionals == 0 => return false,
+                TokenType::Colon if depth == 0 => conditionals -= 1,
+                TokenType::Comma | TokenType::Semicolon if depth == 0 => return true,
+                TokenType::EOF => return true,
+                _ => {}
+            }
+            distance += 1;
+        }
+    }
+
+    // Everything parsed until the matching pop_context() is subject to
+    // `restrictions`. Restrictions don't add up: a parselet that wants to lift
+    // them, like the one for parentheses, pushes Restrictions::NONE
+    pub fn push_context(&mut self, restrictions: Restrictions, description: &'static str) {
+        self.contexts.push((restrictions, description));
+    }
+
+    pub fn pop_context(&mut self) {
+        self.contexts.pop();
+    }
+
+    // Runs `parse` inside a new context
+    pub fn with_context<T>(
+        &mut self,
+        restrictions: Restrictions,
+        description: &'static str,
+        parse: impl FnOnce(&mut Parser<'src, B>) -> T,
+    ) -> T {
+        self.push_context(restrictions, description);
+        let result = parse(self);
+        self.pop_context();
+        result
     }
 
-    fn look_ahead(&mut self, distance: usize) -> Token {
-        while distance >= self.read.len() {
-            self.read.push(self.tokens.next().unwrap());
+    // Returns the description of the current context if it forbids `restriction`
+    pub fn restricted(&self, restriction: Restrictions) -> Option<&'static str> {
+        match self.contexts.last() {
+            Some((restrictions, description)) if restrictions.contains(restriction) => {
+                Some(description)
+            }
+            _ => None,
+        }
+    }
+
+    // Returns the token `distance` tokens ahead without consuming anything
+    pub fn peek(&mut self, distance: usize) -> &Token<'src> {
+        while self.position + distance >= self.read.len() {
+            let token = self.tokens.next().unwrap_or_else(|| {
+                Token::with_span(TokenType::EOF, "", Span::new(self.end, self.end))
+            });
+            self.end = token.get_span().end;
+            self.read.push_back(token);
         }
 
-        return self.read[distance].clone();
+        &self.read[self.position + distance]
     }
 
-    // Helper function to get the precedence of the current token or a default value if there's no infix parselet for the token
-    fn get_precedence(&mut self) -> Precedence {
-        let tok_type: TokenType = *self.look_ahead(0).get_type();
-        if let Some(infix_parser) = self.infix_parselets.get(&tok_type) {
-            infix_parser.get_precedence()
+    // Helper function to get the juxtaposition parselet if the current token
+    // starts a new operand right after `left`. That is the case when the token
+    // has a prefix parselet but no infix one. A number can't be called either,
+    // so `(` right after a number is a juxtaposition too, as in `3(a + b)`
+    fn get_juxtaposition(&mut self, left: &B::Output) -> Option<Arc<dyn InfixParselet<B>>> {
+        self.grammar.get_juxtaposition()?;
+        let tok_type: TokenType = *self.peek(0).get_type();
+        if !self.has_prefix(tok_type) {
+            return None;
+        }
+
+        // `match f x { ... }` must not take the arms for an operand
+        if tok_type == TokenType::LeftBrace && self.restricted(Restrictions::NO_BLOCK).is_some() {
+            return None;
+        }
+
+        if self.grammar.get_infix(tok_type).is_none()
+            || (tok_type == TokenType::LeftParen && self.builder.is_number(left))
+        {
+            self.grammar.get_juxtaposition().cloned()
         } else {
-            Precedence::Everything
+            None
         }
     }
-}
 
-pub struct BantamParser {
-    parser: Parser,
-}
+    // Helper function to get the left binding power of the current token or 0 if there's no infix parselet for the token
+    fn get_binding_power(&mut self) -> u8 {
+        let tok_type: TokenType = *self.peek(0).get_type();
+        // `a?(b)` binds as tightly as a call, `a ?(b) : c` as a conditional
+        if tok_type == TokenType::Question
+            && matches!(
+                self.grammar.get_infix(tok_type),
+                Some(InfixEntry::Conditional(_))
+            )
+            && self.is_optional_call(1)
+        {
+            return Precedence::Call.binding_power();
+        }
 
-impl BantamParser {
-    pub fn new(tokens: Box<dyn Iterator<Item = Token>>) -> Self {
-        let mut bp = Self {
-            parser: Parser::new(tokens),
-        };
+        self.grammar.get_binding_power(tok_type)
+    }
+}
 
-        // Register tokens that need special parselets
-        bp.register_prefix(TokenType::Name, Box::new(NameParselet::new()));
-        bp.register_infix(TokenType::Assign, Box::new(AssignParselet::new()));
-        bp.register_infix(TokenType::Question, Box::new(ConditionalParselet::new()));
-        bp.register_prefix(TokenType::LeftParen, Box::new(GroupParselet::new()));
-        bp.register_infix(TokenType::LeftParen, Box::new(CallParselet::new()));
+pub struct BantamParser<'src, B: ExprBuilder = TreeBuilder> {
+    parser: Parser<'src, B>,
+}
 
-        // Register the simple operator parselets
-        bp.prefix(TokenType::Plus, Precedence::Prefix);
-        bp.prefix(TokenType::Minus, Precedence::Prefix);
-        bp.prefix(TokenType::Tilde, Precedence::Prefix);
-        bp.prefix(TokenType::Bang, Precedence::Prefix);
+impl<'src> BantamParser<'src> {
+    pub fn new(tokens: impl IntoIterator<Item = Token<'src>, IntoIter: Send + 'src>) -> Self {
+        Self::with_grammar(Grammar::bantam(), tokens)
+    }
+}
 
-        // For kicks, we'll make "!" both prefix and postfix, kinda like ++
-        bp.postfix(TokenType::Bang, Precedence::Postfix);
+impl<'src, B: ExprBuilder> BantamParser<'src, B> {
+    pub fn with_grammar(
+        grammar: Grammar<B>,
+        tokens: impl IntoIterator<Item = Token<'src>, IntoIter: Send + 'src>,
+    ) -> Self
+    where
+        B: Default,
+    {
+        Self {
+            parser: grammar.parser(tokens),
+        }
+    }
 
-        bp.infix_left(TokenType::Plus, Precedence::Sum);
-        bp.infix_left(TokenType::Minus, Precedence::Sum);
-        bp.infix_left(TokenType::Asterisk, Precedence::Product);
-        bp.infix_left(TokenType::Slash, Precedence::Product);
-        bp.infix_right(TokenType::Caret, Precedence::Exponent);
+    pub fn with_builder(
+        grammar: Grammar<B>,
+        builder: B,
+        tokens: impl IntoIterator<Item = Token<'src>, IntoIter: Send + 'src>,
+    ) -> Self {
+        Self {
+            parser: grammar.parser_with_builder(builder, tokens),
+        }
+    }
 
-        bp
+    pub fn builder(&mut self) -> &mut B {
+        self.parser.builder()
     }
 
-    pub fn register_prefix(&mut self, tt: TokenType, parselet: Box<dyn PrefixParselet>) -> () {
+    pub fn register_prefix(&mut self, tt: TokenType, parselet: Box<dyn PrefixParselet<B>>) {
         self.parser.register_prefix(tt, parselet);
     }
 
-    pub fn register_infix(&mut self, tt: TokenType, parselet: Box<dyn InfixParselet>) -> () {
+    pub fn register_infix(&mut self, tt: TokenType, parselet: Box<dyn InfixParselet<B>>) {
         self.parser.register_infix(tt, parselet);
     }
 
+    pub fn register_juxtaposition(&mut self, parselet: Box<dyn InfixParselet<B>>) {
+        self.parser.register_juxtaposition(parselet);
+    }
+
     /// Register a prefix unary operator parselet for the given token and precedence
-    pub fn prefix(&mut self, tt: TokenType, precedence: Precedence) -> () {
-        self.register_prefix(tt, Box::new(PrefixOperatorParselet::new(precedence)));
+    pub fn prefix(&mut self, tt: TokenType, precedence: Precedence) {
+        self.parser.grammar_mut().prefix(tt, precedence);
     }
 
     /// Register a postfix unary operator parselet for the given token and precedence
-    pub fn postfix(&mut self, tt: TokenType, precedence: Precedence) -> () {
-        self.register_infix(tt, Box::new(PostfixOperatorParselet::new(precedence)));
+    pub fn postfix(&mut self, tt: TokenType, precedence: Precedence) {
+        self.parser.grammar_mut().postfix(tt, precedence);
     }
 
     /// Register a left-associative binary operator parselet for the given token and precedence
-    pub fn infix_left(&mut self, tt: TokenType, precedence: Precedence) -> () {
-        self.register_infix(tt, Box::new(BinaryOperatorParselet::new(precedence, false)));
+    pub fn infix_left(&mut self, tt: TokenType, precedence: Precedence) {
+        self.parser.grammar_mut().infix_left(tt, precedence);
     }
 
     /// Register a right-associative binary operator parselet for the given token and precedence
-    pub fn infix_right(&mut self, tt: TokenType, precedence: Precedence) -> () {
-        self.register_infix(tt, Box::new(BinaryOperatorParselet::new(precedence, true)));
+    pub fn infix_right(&mut self, tt: TokenType, precedence: Precedence) {
+        self.parser.grammar_mut().infix_right(tt, precedence);
+    }
+
+    /// Register a prefix unary operator parselet with the binding power of its operand
+    pub fn prefix_binding_power(&mut self, tt: TokenType, right_bp: u8) {
+        self.parser.grammar_mut().prefix_binding_power(tt, right_bp);
+    }
+
+    /// Register a postfix unary operator parselet with the binding power of its operand
+    pub fn postfix_binding_power(&mut self, tt: TokenType, left_bp: u8) {
+        self.parser.grammar_mut().postfix_binding_power(tt, left_bp);
     }
 
-    pub fn parse_expression(&mut self) -> Box<dyn Expression> {
+    /// Register a binary operator parselet with a (left, right) binding power pair
+    pub fn infix_binding_power(&mut self, tt: TokenType, left_bp: u8, right_bp: u8) {
+        self.parser
+            .grammar_mut()
+            .infix_binding_power(tt, left_bp, right_bp);
+    }
+
+    pub fn parse_expression(&mut self) -> B::Output {
+        while self.parse_declaration() {}
         self.parser.parse_expression()
     }
+
+    pub fn parse_expression_iterative(&mut self) -> B::Output {
+        while self.parse_declaration() {}
+        self.parser.parse_expression_iterative()
+    }
+
+    // Parses the next of a series of expressions separated by `;`, or returns
+    // None once the input is used up. Tokens are let go of as soon as they are
+    // parsed, so together with a StreamLexer this can work through an input
+    // of any size
+    pub fn parse_statement(&mut self) -> Option<B::Output> {
+        while self.parse_declaration() || self.parser.match_tok(TokenType::Semicolon) {}
+        if *self.parser.peek(0).get_type() == TokenType::EOF {
+            return None;
+        }
+
+        let expression = self.parser.parse_expression();
+        self.parser.match_tok(TokenType::Semicolon);
+        Some(expression)
+    }
+
+    // Operator declarations can come before any expression:
+    //
+    //   infixl 6 <+>
+    //   infixr 8 ^^
+    //   prefix 9 #
+    //   postfix 10 ?!
+    //
+    // The number is the numeric value of a Precedence. The lexer has already
+    // registered the symbol, so here it just has to be added to the parselet
+    // tables. Built-in operators like `+` can be redeclared, but not the
+    // tokens that hold the rest of the grammar together, see
+    // TokenType::is_operator(). Declarations can be separated from what
+    // follows with `;`
+    fn parse_declaration(&mut self) -> bool {
+        let token = self.parser.peek(0);
+        let is_keyword =
+            *token.get_type() == TokenType::Name && is_declaration_keyword(token.get_text());
+        if !is_keyword || *self.parser.peek(1).get_type() != TokenType::Number {
+            return false;
+        }
+
+        let keyword = self.parser.consume();
+        let level = self.parser.consume();
+        // Nothing binds at level 0, an operator declared there would never
+        // get an operand
+        let max = Precedence::Call as usize;
+        let precedence = match level.get_text().parse::<usize>() {
+            Ok(level) if (1..=max).contains(&level) => Precedence::from(level),
+            _ => panic!(
+                "Invalid precedence {} in the declaration of an operator, expected 1 to {}",
+                level.get_text(),
+                max
+            ),
+        };
+
+        let token = self.parser.consume();
+        let operator = *token.get_type();
+        if !operator.is_operator() {
+            panic!(
+                "Expected an operator symbol after {} {} and found {}",
+                keyword.get_text(),
+                level.get_text(),
+                token
+            );
+        }
+
+        match keyword.get_text() {
+            "infixl" => self.infix_left(operator, precedence),
+            "infixr" => self.infix_right(operator, precedence),
+            "prefix" => self.prefix(operator, precedence),
+            _ => self.postfix(operator, precedence),
+        }
+
+        self.parser.match_tok(TokenType::Semicolon);
+        true
+    }
 }
@@ -1,49 +1,205 @@
-use std::any::Any;
+use std::{
+    any::{Any, TypeId},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+};
 
-use crate::core::TokenType;
+use crate::{
+    core::{Span, TokenType},
+    pattern::Pattern,
+};
 
-pub trait Expression {
-    fn print(&self, builder: &mut String) -> ();
+pub trait Expression: DynExpression + Debug + Send + Sync {
+    fn print(&self, builder: &mut String);
 
     fn as_any(&self) -> &dyn Any;
+
+    // Moves the children of this node onto `children`, so that a deep tree can
+    // be dropped one node at a time instead of recursively. Nodes without
+    // children can leave it out
+    fn take_children(&mut self, _children: &mut Vec<Box<dyn Expression>>) {}
+}
+
+// Clone, PartialEq and Hash can't be called through `dyn Expression`, so
+// these stand in for them. There is no need to implement it: every
+// Expression that is Clone, Eq and Hash already is a DynExpression
+pub trait DynExpression {
+    fn clone_box(&self) -> Box<dyn Expression>;
+
+    // Nodes of different types are never equal
+    fn eq_dyn(&self, other: &dyn Expression) -> bool;
+
+    fn hash_dyn(&self, state: &mut dyn Hasher);
+}
+
+impl<T: Expression + Clone + Eq + Hash + 'static> DynExpression for T {
+    fn clone_box(&self) -> Box<dyn Expression> {
+        Box::new(self.clone())
+    }
+
+    fn eq_dyn(&self, other: &dyn Expression) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(self)
+    }
+
+    fn hash_dyn(&self, mut state: &mut dyn Hasher) {
+        TypeId::of::<T>().hash(&mut state);
+        self.hash(&mut state);
+    }
+}
+
+impl Clone for Box<dyn Expression> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+impl PartialEq for dyn Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_dyn(other)
+    }
+}
+
+impl Eq for dyn Expression {}
+
+// Only here so that #[derive(PartialEq)] works on nodes with a
+// Box<dyn Expression> in them. Without it the derived code tries to move the
+// box out of the node, see rust-lang/rust#31740
+impl PartialEq<&Self> for Box<dyn Expression> {
+    fn eq(&self, other: &&Self) -> bool {
+        **self == ***other
+    }
+}
+
+impl Hash for dyn Expression {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_dyn(state);
+    }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct NameExpression {
     name: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NumberExpression {
+    value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PrefixExpression {
     operator: TokenType,
     right: Box<dyn Expression>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct OperatorExpression {
     left: Box<dyn Expression>,
     operator: TokenType,
     right: Box<dyn Expression>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PostfixExpression {
     left: Box<dyn Expression>,
     operator: TokenType,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ConditionalExpression {
     condition: Box<dyn Expression>,
     then_arm: Box<dyn Expression>,
     else_arm: Box<dyn Expression>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AssignExpression {
     name: String,
     right: Box<dyn Expression>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CallExpression {
     function: Box<dyn Expression>,
     args: Vec<Box<dyn Expression>>,
 }
 
+// (a, b), (a,), ()
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TupleExpression {
+    elements: Vec<Box<dyn Expression>>,
+}
+
+// (a, b) -> a + b
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LambdaExpression {
+    params: Vec<String>,
+    body: Box<dyn Expression>,
+}
+
+// (+), (* 2), (2 *)
+// A binary operator with one or both operands left out, to be filled in
+// when the section is applied
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SectionExpression {
+    left: Option<Box<dyn Expression>>,
+    operator: TokenType,
+    right: Option<Box<dyn Expression>>,
+}
+
+// a?.b
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OptionalMemberExpression {
+    object: Box<dyn Expression>,
+    name: String,
+}
+
+// a?(b, c)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OptionalCallExpression {
+    function: Box<dyn Expression>,
+    args: Vec<Box<dyn Expression>>,
+}
+
+// x |> f |> g(1)
+// Feeds the value on the left into the function on the right
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineExpression {
+    value: Box<dyn Expression>,
+    function: Box<dyn Expression>,
+}
+
+// match x { 0 -> a, 1 -> b, _ -> c }
+// Arms are tried in order and the first one whose pattern matches is taken
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MatchExpression {
+    value: Box<dyn Expression>,
+    arms: Vec<MatchArm>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MatchArm {
+    pattern: Pattern,
+    body: Box<dyn Expression>,
+}
+
+// let x = a + b in x * x
+// The binding is always an AssignExpression and the name it assigns is only
+// visible inside the body
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LetExpression {
+    binding: Box<dyn Expression>,
+    body: Box<dyn Expression>,
+}
+
+// { a = 1; b = a + 2; b * 2 }
+// Names assigned inside a block are local to it and the value of the block is
+// the value of its last statement
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BlockExpression {
+    statements: Vec<Box<dyn Expression>>,
+}
+
 impl NameExpression {
     pub fn new(name: String) -> Self {
         Self { name }
@@ -55,7 +211,7 @@ impl NameExpression {
 }
 
 impl Expression for NameExpression {
-    fn print(&self, builder: &mut String) -> () {
+    fn print(&self, builder: &mut String) {
         builder.push_str(&self.name);
     }
 
@@ -64,6 +220,26 @@ impl Expression for NameExpression {
     }
 }
 
+impl NumberExpression {
+    pub fn new(value: String) -> Self {
+        Self { value }
+    }
+
+    pub fn value(&self) -> &String {
+        &self.value
+    }
+}
+
+impl Expression for NumberExpression {
+    fn print(&self, builder: &mut String) {
+        builder.push_str(&self.value);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
 impl PrefixExpression {
     pub fn new(tt: TokenType, right: Box<dyn Expression>) -> Self {
         Self {
@@ -71,19 +247,31 @@ impl PrefixExpression {
             right,
         }
     }
+
+    pub fn operator(&self) -> TokenType {
+        self.operator
+    }
+
+    pub fn right(&self) -> &dyn Expression {
+        self.right.as_ref()
+    }
 }
 
 impl Expression for PrefixExpression {
-    fn print(&self, builder: &mut String) -> () {
-        builder.push_str("(");
-        builder.push(self.operator.punctuator().unwrap());
+    fn print(&self, builder: &mut String) {
+        builder.push('(');
+        builder.push_str(self.operator.punctuator().unwrap());
         self.right.print(builder);
-        builder.push_str(")");
+        builder.push(')');
     }
 
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.right, children);
+    }
 }
 
 impl OperatorExpression {
@@ -94,41 +282,70 @@ impl OperatorExpression {
             right,
         }
     }
+
+    pub fn left(&self) -> &dyn Expression {
+        self.left.as_ref()
+    }
+
+    pub fn operator(&self) -> TokenType {
+        self.operator
+    }
+
+    pub fn right(&self) -> &dyn Expression {
+        self.right.as_ref()
+    }
 }
 
 impl Expression for OperatorExpression {
-    fn print(&self, builder: &mut String) -> () {
-        builder.push_str("(");
+    fn print(&self, builder: &mut String) {
+        builder.push('(');
         self.left.print(builder);
-        builder.push_str(" ");
-        builder.push(self.operator.punctuator().unwrap());
-        builder.push_str(" ");
+        builder.push(' ');
+        builder.push_str(self.operator.punctuator().unwrap());
+        builder.push(' ');
         self.right.print(builder);
-        builder.push_str(")");
+        builder.push(')');
     }
 
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.left, children);
+        take(&mut self.right, children);
+    }
 }
 
 impl PostfixExpression {
     pub fn new(left: Box<dyn Expression>, operator: TokenType) -> Self {
         Self { left, operator }
     }
+
+    pub fn left(&self) -> &dyn Expression {
+        self.left.as_ref()
+    }
+
+    pub fn operator(&self) -> TokenType {
+        self.operator
+    }
 }
 
 impl Expression for PostfixExpression {
-    fn print(&self, builder: &mut String) -> () {
-        builder.push_str("(");
+    fn print(&self, builder: &mut String) {
+        builder.push('(');
         self.left.print(builder);
-        builder.push(self.operator.punctuator().unwrap());
-        builder.push_str(")");
+        builder.push_str(self.operator.punctuator().unwrap());
+        builder.push(')');
     }
 
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.left, children);
+    }
 }
 
 impl ConditionalExpression {
@@ -143,66 +360,1140 @@ impl ConditionalExpression {
             else_arm,
         }
     }
+
+    pub fn condition(&self) -> &dyn Expression {
+        self.condition.as_ref()
+    }
+
+    pub fn then_arm(&self) -> &dyn Expression {
+        self.then_arm.as_ref()
+    }
+
+    pub fn else_arm(&self) -> &dyn Expression {
+        self.else_arm.as_ref()
+    }
 }
 
 impl Expression for ConditionalExpression {
-    fn print(&self, builder: &mut String) -> () {
-        builder.push_str("(");
+    fn print(&self, builder: &mut String) {
+        builder.push('(');
         self.condition.print(builder);
         builder.push_str(" ? ");
         self.then_arm.print(builder);
         builder.push_str(" : ");
         self.else_arm.print(builder);
-        builder.push_str(")");
+        builder.push(')');
     }
 
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.condition, children);
+        take(&mut self.then_arm, children);
+        take(&mut self.else_arm, children);
+    }
 }
 
 impl AssignExpression {
     pub fn new(name: String, right: Box<dyn Expression>) -> Self {
         Self { name, right }
     }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn right(&self) -> &dyn Expression {
+        self.right.as_ref()
+    }
 }
 
 impl Expression for AssignExpression {
-    fn print(&self, builder: &mut String) -> () {
-        builder.push_str("(");
+    fn print(&self, builder: &mut String) {
+        builder.push('(');
         builder.push_str(&self.name);
         builder.push_str(" = ");
         self.right.print(builder);
-        builder.push_str(")");
+        builder.push(')');
     }
 
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.right, children);
+    }
 }
 
 impl CallExpression {
     pub fn new(function: Box<dyn Expression>, args: Vec<Box<dyn Expression>>) -> Self {
         Self { function, args }
     }
+
+    pub fn function(&self) -> &dyn Expression {
+        self.function.as_ref()
+    }
+
+    pub fn args(&self) -> &[Box<dyn Expression>] {
+        &self.args
+    }
 }
 
 impl Expression for CallExpression {
-    fn print(&self, builder: &mut String) -> () {
+    fn print(&self, builder: &mut String) {
         self.function.print(builder);
-        builder.push_str("(");
-        let mut i = 0;
-        for arg in self.args.iter() {
+        builder.push('(');
+        for (i, arg) in self.args.iter().enumerate() {
             arg.print(builder);
             if i + 1 < self.args.len() {
                 builder.push_str(", ");
             }
-            i += 1;
         }
-        builder.push_str(")");
+        builder.push(')');
     }
 
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.function, children);
+        children.append(&mut self.args);
+    }
+}
+
+impl LetExpression {
+    pub fn new(binding: Box<dyn Expression>, body: Box<dyn Expression>) -> Self {
+        Self { binding, body }
+    }
+
+    pub fn binding(&self) -> &dyn Expression {
+        self.binding.as_ref()
+    }
+
+    pub fn body(&self) -> &dyn Expression {
+        self.body.as_ref()
+    }
+}
+
+impl Expression for LetExpression {
+    fn print(&self, builder: &mut String) {
+        builder.push_str("(let ");
+        self.binding.print(builder);
+        builder.push_str(" in ");
+        self.body.print(builder);
+        builder.push(')');
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.binding, children);
+        take(&mut self.body, children);
+    }
+}
+
+impl BlockExpression {
+    pub fn new(statements: Vec<Box<dyn Expression>>) -> Self {
+        Self { statements }
+    }
+
+    pub fn statements(&self) -> &[Box<dyn Expression>] {
+        &self.statements
+    }
+}
+
+impl Expression for BlockExpression {
+    fn print(&self, builder: &mut String) {
+        builder.push('{');
+        for (i, statement) in self.statements.iter().enumerate() {
+            builder.push(' ');
+            statement.print(builder);
+            if i + 1 < self.statements.len() {
+                builder.push(';');
+            }
+        }
+        builder.push_str(" }");
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        children.append(&mut self.statements);
+    }
+}
+
+impl TupleExpression {
+    pub fn new(elements: Vec<Box<dyn Expression>>) -> Self {
+        Self { elements }
+    }
+
+    pub fn elements(&self) -> &[Box<dyn Expression>] {
+        &self.elements
+    }
+}
+
+impl Expression for TupleExpression {
+    fn print(&self, builder: &mut String) {
+        builder.push('(');
+        for (i, element) in self.elements.iter().enumerate() {
+            element.print(builder);
+            if i + 1 < self.elements.len() {
+                builder.push_str(", ");
+            }
+        }
+        // A single element needs the comma to still read as a tuple
+        if self.elements.len() == 1 {
+            builder.push(',');
+        }
+        builder.push(')');
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        children.append(&mut self.elements);
+    }
+}
+
+impl LambdaExpression {
+    pub fn new(params: Vec<String>, body: Box<dyn Expression>) -> Self {
+        Self { params, body }
+    }
+
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    pub fn body(&self) -> &dyn Expression {
+        self.body.as_ref()
+    }
+}
+
+impl Expression for LambdaExpression {
+    fn print(&self, builder: &mut String) {
+        builder.push_str("((");
+        builder.push_str(&self.params.join(", "));
+        builder.push_str(") -> ");
+        self.body.print(builder);
+        builder.push(')');
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.body, children);
+    }
+}
+
+impl SectionExpression {
+    pub fn new(
+        left: Option<Box<dyn Expression>>,
+        operator: TokenType,
+        right: Option<Box<dyn Expression>>,
+    ) -> Self {
+        Self {
+            left,
+            operator,
+            right,
+        }
+    }
+
+    pub fn left(&self) -> Option<&dyn Expression> {
+        self.left.as_deref()
+    }
+
+    pub fn operator(&self) -> TokenType {
+        self.operator
+    }
+
+    pub fn right(&self) -> Option<&dyn Expression> {
+        self.right.as_deref()
+    }
+}
+
+impl Expression for SectionExpression {
+    fn print(&self, builder: &mut String) {
+        builder.push('(');
+        if let Some(left) = &self.left {
+            left.print(builder);
+            builder.push(' ');
+        }
+        builder.push_str(self.operator.punctuator().unwrap());
+        if let Some(right) = &self.right {
+            builder.push(' ');
+            right.print(builder);
+        }
+        builder.push(')');
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        children.extend(self.left.take());
+        children.extend(self.right.take());
+    }
+}
+
+impl OptionalMemberExpression {
+    pub fn new(object: Box<dyn Expression>, name: String) -> Self {
+        Self { object, name }
+    }
+
+    pub fn object(&self) -> &dyn Expression {
+        self.object.as_ref()
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+}
+
+impl Expression for OptionalMemberExpression {
+    fn print(&self, builder: &mut String) {
+        self.object.print(builder);
+        builder.push_str("?.");
+        builder.push_str(&self.name);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.object, children);
+    }
+}
+
+impl OptionalCallExpression {
+    pub fn new(function: Box<dyn Expression>, args: Vec<Box<dyn Expression>>) -> Self {
+        Self { function, args }
+    }
+
+    pub fn function(&self) -> &dyn Expression {
+        self.function.as_ref()
+    }
+
+    pub fn args(&self) -> &[Box<dyn Expression>] {
+        &self.args
+    }
+}
+
+impl Expression for OptionalCallExpression {
+    fn print(&self, builder: &mut String) {
+        self.function.print(builder);
+        builder.push_str("?(");
+        for (i, arg) in self.args.iter().enumerate() {
+            arg.print(builder);
+            if i + 1 < self.args.len() {
+                builder.push_str(", ");
+            }
+        }
+        builder.push(')');
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.function, children);
+        children.append(&mut self.args);
+    }
+}
+
+impl PipelineExpression {
+    pub fn new(value: Box<dyn Expression>, function: Box<dyn Expression>) -> Self {
+        Self { value, function }
+    }
+
+    pub fn value(&self) -> &dyn Expression {
+        self.value.as_ref()
+    }
+
+    pub fn function(&self) -> &dyn Expression {
+        self.function.as_ref()
+    }
+}
+
+impl Expression for PipelineExpression {
+    fn print(&self, builder: &mut String) {
+        builder.push('(');
+        self.value.print(builder);
+        builder.push_str(" |> ");
+        self.function.print(builder);
+        builder.push(')');
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.value, children);
+        take(&mut self.function, children);
+    }
+}
+
+impl MatchExpression {
+    pub fn new(value: Box<dyn Expression>, arms: Vec<MatchArm>) -> Self {
+        Self { value, arms }
+    }
+
+    pub fn value(&self) -> &dyn Expression {
+        self.value.as_ref()
+    }
+
+    pub fn arms(&self) -> &[MatchArm] {
+        &self.arms
+    }
+}
+
+impl Expression for MatchExpression {
+    fn print(&self, builder: &mut String) {
+        builder.push_str("(match ");
+        self.value.print(builder);
+        builder.push_str(" {");
+        for (i, arm) in self.arms.iter().enumerate() {
+            builder.push(' ');
+            arm.pattern.print(builder);
+            builder.push_str(" -> ");
+            arm.body.print(builder);
+            if i + 1 < self.arms.len() {
+                builder.push(',');
+            }
+        }
+        builder.push_str(" })");
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn take_children(&mut self, children: &mut Vec<Box<dyn Expression>>) {
+        take(&mut self.value, children);
+        for arm in &mut self.arms {
+            take(&mut arm.body, children);
+        }
+    }
+}
+
+impl MatchArm {
+    pub fn new(pattern: Pattern, body: Box<dyn Expression>) -> Self {
+        Self { pattern, body }
+    }
+
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    pub fn body(&self) -> &dyn Expression {
+        self.body.as_ref()
+    }
+}
+
+// Without these, dropping a tree would recurse once per level and a deep
+// enough tree, like the ones parse_expression_iterative() can build, would
+// overflow the stack. Each node moves its children onto a stack on the heap
+// before it goes, so by the time a child is dropped it has none left
+macro_rules! drop_iteratively {
+    ($($node:ty),*) => {
+        $(
+            impl Drop for $node {
+                fn drop(&mut self) {
+                    let mut stack = Vec::new();
+                    self.take_children(&mut stack);
+                    while let Some(mut child) = stack.pop() {
+                        child.take_children(&mut stack);
+                    }
+                }
+            }
+        )*
+    };
+}
+
+drop_iteratively!(
+    PrefixExpression,
+    OperatorExpression,
+    PostfixExpression,
+    ConditionalExpression,
+    AssignExpression,
+    CallExpression,
+    LetExpression,
+    BlockExpression,
+    TupleExpression,
+    LambdaExpression,
+    SectionExpression,
+    OptionalMemberExpression,
+    OptionalCallExpression,
+    PipelineExpression,
+    MatchExpression
+);
+
+// What is left in a node where a child was taken out. It has no size, so
+// boxing it doesn't allocate
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Taken;
+
+impl Expression for Taken {
+    fn print(&self, _builder: &mut String) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// Skips children that were already taken, so that a node dropped off the
+// stack doesn't put its placeholders on a stack of its own, which would
+// allocate
+fn take(child: &mut Box<dyn Expression>, children: &mut Vec<Box<dyn Expression>>) {
+    if !child.as_any().is::<Taken>() {
+        children.push(std::mem::replace(child, Box::new(Taken)));
+    }
+}
+
+// Where in the source an Expr was parsed from, if it is known. It is left out
+// when comparing and hashing, so `a+b` and `a + b` give equal expressions
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct SourceSpan(pub Option<Span>);
+
+impl SourceSpan {
+    pub fn is_none(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+impl PartialEq for SourceSpan {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SourceSpan {}
+
+impl Hash for SourceSpan {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+// The same expressions as a plain enum with public fields, for code that
+// wants to match on the kind of expression instead of downcasting. Build it
+// straight from the parser with the AstBuilder, which also records the span
+// of every node, or convert a tree of Expression nodes with Expr::from,
+// which can't. See src/json.rs for its JSON form
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "kind", rename_all = "snake_case")
+)]
+pub enum Expr {
+    Name {
+        name: String,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Number {
+        value: String,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Prefix {
+        #[cfg_attr(feature = "serde", serde(with = "crate::json::operator"))]
+        operator: TokenType,
+        right: Box<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Binary {
+        left: Box<Expr>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::json::operator"))]
+        operator: TokenType,
+        right: Box<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Postfix {
+        left: Box<Expr>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::json::operator"))]
+        operator: TokenType,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Conditional {
+        condition: Box<Expr>,
+        then_arm: Box<Expr>,
+        else_arm: Box<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Assign {
+        name: String,
+        right: Box<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Call {
+        function: Box<Expr>,
+        args: Vec<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Tuple {
+        elements: Vec<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Lambda {
+        params: Vec<String>,
+        body: Box<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Section {
+        left: Option<Box<Expr>>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::json::operator"))]
+        operator: TokenType,
+        right: Option<Box<Expr>>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    OptionalMember {
+        object: Box<Expr>,
+        name: String,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    OptionalCall {
+        function: Box<Expr>,
+        args: Vec<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Pipeline {
+        value: Box<Expr>,
+        function: Box<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Match {
+        value: Box<Expr>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::json::arms"))]
+        arms: Vec<(Pattern, Expr)>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    // Unlike LetExpression this holds the parts of the binding, since it
+    // can't be anything but an assignment
+    Let {
+        name: String,
+        value: Box<Expr>,
+        body: Box<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+    Block {
+        statements: Vec<Expr>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "SourceSpan::is_none")
+        )]
+        span: SourceSpan,
+    },
+}
+
+impl Expr {
+    pub fn span(&self) -> Option<Span> {
+        self.source_span().0
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        *self.source_span_mut() = SourceSpan(Some(span));
+    }
+
+    fn source_span(&self) -> &SourceSpan {
+        match self {
+            Expr::Name { span, .. }
+            | Expr::Number { span, .. }
+            | Expr::Prefix { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Postfix { span, .. }
+            | Expr::Conditional { span, .. }
+            | Expr::Assign { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Tuple { span, .. }
+            | Expr::Lambda { span, .. }
+            | Expr::Section { span, .. }
+            | Expr::OptionalMember { span, .. }
+            | Expr::OptionalCall { span, .. }
+            | Expr::Pipeline { span, .. }
+            | Expr::Match { span, .. }
+            | Expr::Let { span, .. }
+            | Expr::Block { span, .. } => span,
+        }
+    }
+
+    fn source_span_mut(&mut self) -> &mut SourceSpan {
+        match self {
+            Expr::Name { span, .. }
+            | Expr::Number { span, .. }
+            | Expr::Prefix { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Postfix { span, .. }
+            | Expr::Conditional { span, .. }
+            | Expr::Assign { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Tuple { span, .. }
+            | Expr::Lambda { span, .. }
+            | Expr::Section { span, .. }
+            | Expr::OptionalMember { span, .. }
+            | Expr::OptionalCall { span, .. }
+            | Expr::Pipeline { span, .. }
+            | Expr::Match { span, .. }
+            | Expr::Let { span, .. }
+            | Expr::Block { span, .. } => span,
+        }
+    }
+
+    fn print_list(items: &[Expr], builder: &mut String) {
+        for (i, item) in items.iter().enumerate() {
+            item.print(builder);
+            if i + 1 < items.len() {
+                builder.push_str(", ");
+            }
+        }
+    }
+}
+
+// Prints the same as the Expression node it corresponds to
+impl Expression for Expr {
+    fn print(&self, builder: &mut String) {
+        match self {
+            Expr::Name { name, .. } => builder.push_str(name),
+            Expr::Number { value, .. } => builder.push_str(value),
+            Expr::Prefix {
+                operator, right, ..
+            } => {
+                builder.push('(');
+                builder.push_str(operator.punctuator().unwrap());
+                right.print(builder);
+                builder.push(')');
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                builder.push('(');
+                left.print(builder);
+                builder.push(' ');
+                builder.push_str(operator.punctuator().unwrap());
+                builder.push(' ');
+                right.print(builder);
+                builder.push(')');
+            }
+            Expr::Postfix { left, operator, .. } => {
+                builder.push('(');
+                left.print(builder);
+                builder.push_str(operator.punctuator().unwrap());
+                builder.push(')');
+            }
+            Expr::Conditional {
+                condition,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                builder.push('(');
+                condition.print(builder);
+                builder.push_str(" ? ");
+                then_arm.print(builder);
+                builder.push_str(" : ");
+                else_arm.print(builder);
+                builder.push(')');
+            }
+            Expr::Assign { name, right, .. } => {
+                builder.push('(');
+                builder.push_str(name);
+                builder.push_str(" = ");
+                right.print(builder);
+                builder.push(')');
+            }
+            Expr::Call { function, args, .. } => {
+                function.print(builder);
+                builder.push('(');
+                Expr::print_list(args, builder);
+                builder.push(')');
+            }
+            Expr::Tuple { elements, .. } => {
+                builder.push('(');
+                Expr::print_list(elements, builder);
+                if elements.len() == 1 {
+                    builder.push(',');
+                }
+                builder.push(')');
+            }
+            Expr::Lambda { params, body, .. } => {
+                builder.push_str("((");
+                builder.push_str(&params.join(", "));
+                builder.push_str(") -> ");
+                body.print(builder);
+                builder.push(')');
+            }
+            Expr::Section {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                builder.push('(');
+                if let Some(left) = left {
+                    left.print(builder);
+                    builder.push(' ');
+                }
+                builder.push_str(operator.punctuator().unwrap());
+                if let Some(right) = right {
+                    builder.push(' ');
+                    right.print(builder);
+                }
+                builder.push(')');
+            }
+            Expr::OptionalMember { object, name, .. } => {
+                object.print(builder);
+                builder.push_str("?.");
+                builder.push_str(name);
+            }
+            Expr::OptionalCall { function, args, .. } => {
+                function.print(builder);
+                builder.push_str("?(");
+                Expr::print_list(args, builder);
+                builder.push(')');
+            }
+            Expr::Pipeline {
+                value, function, ..
+            } => {
+                builder.push('(');
+                value.print(builder);
+                builder.push_str(" |> ");
+                function.print(builder);
+                builder.push(')');
+            }
+            Expr::Match { value, arms, .. } => {
+                builder.push_str("(match ");
+                value.print(builder);
+                builder.push_str(" {");
+                for (i, (pattern, body)) in arms.iter().enumerate() {
+                    builder.push(' ');
+                    pattern.print(builder);
+                    builder.push_str(" -> ");
+                    body.print(builder);
+                    if i + 1 < arms.len() {
+                        builder.push(',');
+                    }
+                }
+                builder.push_str(" })");
+            }
+            Expr::Let {
+                name, value, body, ..
+            } => {
+                builder.push_str("(let (");
+                builder.push_str(name);
+                builder.push_str(" = ");
+                value.print(builder);
+                builder.push_str(") in ");
+                body.print(builder);
+                builder.push(')');
+            }
+            Expr::Block { statements, .. } => {
+                builder.push('{');
+                for (i, statement) in statements.iter().enumerate() {
+                    builder.push(' ');
+                    statement.print(builder);
+                    if i + 1 < statements.len() {
+                        builder.push(';');
+                    }
+                }
+                builder.push_str(" }");
+            }
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+// Copies a tree of the built-in Expression nodes. Nodes from outside the
+// crate can't be converted
+impl From<&dyn Expression> for Expr {
+    fn from(expression: &dyn Expression) -> Self {
+        let any = expression.as_any();
+        let boxed = |e: &dyn Expression| Box::new(Expr::from(e));
+        let list = |es: &[Box<dyn Expression>]| es.iter().map(|e| Expr::from(e.as_ref())).collect();
+
+        if let Some(e) = any.downcast_ref::<Expr>() {
+            e.clone()
+        } else if let Some(e) = any.downcast_ref::<NameExpression>() {
+            Expr::Name {
+                name: e.name.clone(),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<NumberExpression>() {
+            Expr::Number {
+                value: e.value.clone(),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<PrefixExpression>() {
+            Expr::Prefix {
+                operator: e.operator,
+                right: boxed(e.right.as_ref()),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<OperatorExpression>() {
+            Expr::Binary {
+                left: boxed(e.left.as_ref()),
+                operator: e.operator,
+                right: boxed(e.right.as_ref()),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<PostfixExpression>() {
+            Expr::Postfix {
+                left: boxed(e.left.as_ref()),
+                operator: e.operator,
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<ConditionalExpression>() {
+            Expr::Conditional {
+                condition: boxed(e.condition.as_ref()),
+                then_arm: boxed(e.then_arm.as_ref()),
+                else_arm: boxed(e.else_arm.as_ref()),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<AssignExpression>() {
+            Expr::Assign {
+                name: e.name.clone(),
+                right: boxed(e.right.as_ref()),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<CallExpression>() {
+            Expr::Call {
+                function: boxed(e.function.as_ref()),
+                args: list(&e.args),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<TupleExpression>() {
+            Expr::Tuple {
+                elements: list(&e.elements),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<LambdaExpression>() {
+            Expr::Lambda {
+                params: e.params.clone(),
+                body: boxed(e.body.as_ref()),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<SectionExpression>() {
+            Expr::Section {
+                left: e.left.as_deref().map(boxed),
+                operator: e.operator,
+                right: e.right.as_deref().map(boxed),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<OptionalMemberExpression>() {
+            Expr::OptionalMember {
+                object: boxed(e.object.as_ref()),
+                name: e.name.clone(),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<OptionalCallExpression>() {
+            Expr::OptionalCall {
+                function: boxed(e.function.as_ref()),
+                args: list(&e.args),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<PipelineExpression>() {
+            Expr::Pipeline {
+                value: boxed(e.value.as_ref()),
+                function: boxed(e.function.as_ref()),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<MatchExpression>() {
+            Expr::Match {
+                value: boxed(e.value.as_ref()),
+                arms: e
+                    .arms
+                    .iter()
+                    .map(|arm| (arm.pattern.clone(), Expr::from(arm.body.as_ref())))
+                    .collect(),
+                span: SourceSpan::default(),
+            }
+        } else if let Some(e) = any.downcast_ref::<LetExpression>() {
+            match Expr::from(e.binding.as_ref()) {
+                Expr::Assign { name, right, .. } => Expr::Let {
+                    name,
+                    value: right,
+                    body: boxed(e.body.as_ref()),
+                    span: SourceSpan::default(),
+                },
+                _ => panic!("let must be followed by an assignment"),
+            }
+        } else if let Some(e) = any.downcast_ref::<BlockExpression>() {
+            Expr::Block {
+                statements: list(&e.statements),
+                span: SourceSpan::default(),
+            }
+        } else {
+            let mut printed = String::new();
+            expression.print(&mut printed);
+            panic!("Could not convert {printed} to an Expr.");
+        }
+    }
+}
+
+impl From<Box<dyn Expression>> for Expr {
+    fn from(expression: Box<dyn Expression>) -> Self {
+        Expr::from(expression.as_ref())
+    }
+}
+
+// And back again, into the tree the TreeBuilder would have built
+impl From<Expr> for Box<dyn Expression> {
+    fn from(expr: Expr) -> Self {
+        let tree = |expr: Box<Expr>| Box::<dyn Expression>::from(*expr);
+        let list = |exprs: Vec<Expr>| exprs.into_iter().map(Box::<dyn Expression>::from).collect();
+
+        match expr {
+            Expr::Name { name, .. } => Box::new(NameExpression::new(name)),
+            Expr::Number { value, .. } => Box::new(NumberExpression::new(value)),
+            Expr::Prefix {
+                operator, right, ..
+            } => Box::new(PrefixExpression::new(operator, tree(right))),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => Box::new(OperatorExpression::new(tree(left), operator, tree(right))),
+            Expr::Postfix { left, operator, .. } => {
+                Box::new(PostfixExpression::new(tree(left), operator))
+            }
+            Expr::Conditional {
+                condition,
+                then_arm,
+                else_arm,
+                ..
+            } => Box::new(ConditionalExpression::new(
+                tree(condition),
+                tree(then_arm),
+                tree(else_arm),
+            )),
+            Expr::Assign { name, right, .. } => Box::new(AssignExpression::new(name, tree(right))),
+            Expr::Call { function, args, .. } => {
+                Box::new(CallExpression::new(tree(function), list(args)))
+            }
+            Expr::Tuple { elements, .. } => Box::new(TupleExpression::new(list(elements))),
+            Expr::Lambda { params, body, .. } => {
+                Box::new(LambdaExpression::new(params, tree(body)))
+            }
+            Expr::Section {
+                left,
+                operator,
+                right,
+                ..
+            } => Box::new(SectionExpression::new(
+                left.map(tree),
+                operator,
+                right.map(tree),
+            )),
+            Expr::OptionalMember { object, name, .. } => {
+                Box::new(OptionalMemberExpression::new(tree(object), name))
+            }
+            Expr::OptionalCall { function, args, .. } => {
+                Box::new(OptionalCallExpression::new(tree(function), list(args)))
+            }
+            Expr::Pipeline {
+                value, function, ..
+            } => Box::new(PipelineExpression::new(tree(value), tree(function))),
+            Expr::Match { value, arms, .. } => {
+                let arms = arms
+                    .into_iter()
+                    .map(|(pattern, body)| MatchArm::new(pattern, body.into()))
+                    .collect();
+                Box::new(MatchExpression::new(tree(value), arms))
+            }
+            Expr::Let {
+                name, value, body, ..
+            } => {
+                let binding = Box::new(AssignExpression::new(name, tree(value)));
+                Box::new(LetExpression::new(binding, tree(body)))
+            }
+            Expr::Block { statements, .. } => Box::new(BlockExpression::new(list(statements))),
+        }
+    }
 }
@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    panic,
+    sync::{Arc, OnceLock},
+    thread,
+};
+
+use crate::{
+    builder::{ExprBuilder, TreeBuilder},
+    core::{BantamParser, Lexer, Parser, Precedence, Token, TokenType},
+    parselet::{
+        AssignParselet, BinaryOperatorParselet, BlockParselet, CallParselet, ConditionalParselet,
+        GroupParselet, InfixEntry, InfixParselet, LetParselet, MatchParselet, NameParselet,
+        NumberParselet, OptionalMemberParselet, PipelineParselet, PostfixOperatorParselet,
+        PrefixEntry, PrefixOperatorParselet, PrefixParselet,
+    },
+};
+
+// The parselet tables that make up a language. A Grammar is built once and
+// every Parser made from it shares its tables, so starting a parse doesn't
+// register anything. Cloning a Grammar is cheap for the same reason: the
+// tables are only copied when one of the clones registers a parselet, like a
+// parser does for operators declared in the source
+//
+// The builder decides what the parsers made from the grammar produce, see
+// ExprBuilder. It only takes part as a type, a Grammar doesn't hold one
+pub struct Grammar<B: ExprBuilder = TreeBuilder> {
+    tables: Arc<Tables<B>>,
+}
+
+// The tables are indexed by TokenType::index(), so looking up a parselet
+// doesn't hash anything. Declared operators don't have an index and are kept
+// in maps on the side
+struct Tables<B: ExprBuilder> {
+    // We have separate tables for prefix and infix expressions because sometimes we have both a prefix and infix parselet for the same TokenType. For example, the prefix parselet for `(` handles grouping in an expression like `a * (b + c)`. Meanwhile the infix parselet for `(` handles function calls like `a(b)`
+    prefix_parselets: Vec<Option<PrefixEntry<B>>>,
+    infix_parselets: Vec<Option<InfixEntry<B>>>,
+
+    // The left binding power of every infix parselet, or 0 if there is none.
+    // The parser checks it after every operand, so it is worth caching
+    binding_powers: Vec<u8>,
+
+    operator_prefix_parselets: HashMap<TokenType, PrefixEntry<B>>,
+    operator_infix_parselets: HashMap<TokenType, InfixEntry<B>>,
+
+    // Applied when two operands sit next to each other with no operator in
+    // between, like `2x` or `f a`. Off unless one is registered
+    juxtaposition: Option<Arc<dyn InfixParselet<B>>>,
+}
+
+// Clone and Default are implemented by hand, deriving them would require the
+// builder to implement them too
+impl<B: ExprBuilder> Clone for Grammar<B> {
+    fn clone(&self) -> Self {
+        Self {
+            tables: self.tables.clone(),
+        }
+    }
+}
+
+impl<B: ExprBuilder> Default for Grammar<B> {
+    fn default() -> Self {
+        Self {
+            tables: Arc::default(),
+        }
+    }
+}
+
+impl<B: ExprBuilder> Clone for Tables<B> {
+    fn clone(&self) -> Self {
+        Self {
+            prefix_parselets: self.prefix_parselets.clone(),
+            infix_parselets: self.infix_parselets.clone(),
+            binding_powers: self.binding_powers.clone(),
+            operator_prefix_parselets: self.operator_prefix_parselets.clone(),
+            operator_infix_parselets: self.operator_infix_parselets.clone(),
+            juxtaposition: self.juxtaposition.clone(),
+        }
+    }
+}
+
+impl<B: ExprBuilder> Default for Tables<B> {
+    fn default() -> Self {
+        Self {
+            prefix_parselets: vec![None; TokenType::COUNT],
+            infix_parselets: vec![None; TokenType::COUNT],
+            binding_powers: vec![0; TokenType::COUNT],
+            operator_prefix_parselets: HashMap::new(),
+            operator_infix_parselets: HashMap::new(),
+            juxtaposition: None,
+        }
+    }
+}
+
+// These two are only for the expression tree, so that `Grammar::new()` and
+// `Grammar::bantam()` don't need the builder spelled out
+impl Grammar {
+    // An empty grammar, which can't parse anything until parselets are registered
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The grammar of the Bantam language. It is only built once, after that
+    // this is a clone of the same tables
+    pub fn bantam() -> Self {
+        static BANTAM: OnceLock<Grammar> = OnceLock::new();
+        BANTAM.get_or_init(Grammar::build_bantam).clone()
+    }
+}
+
+impl<B: ExprBuilder> Grammar<B> {
+    // The grammar of the Bantam language for any builder. Unlike
+    // Grammar::bantam() this builds the tables anew every time
+    pub fn build_bantam() -> Self {
+        let mut grammar = Self::default();
+
+        // Register tokens that need special parselets
+        grammar.register_prefix_entry(TokenType::Name, PrefixEntry::Name(NameParselet::new()));
+        grammar.register_prefix_entry(
+            TokenType::Number,
+            PrefixEntry::Number(NumberParselet::new()),
+        );
+        grammar.register_prefix_entry(TokenType::Let, PrefixEntry::Let(LetParselet::new()));
+        grammar.register_prefix_entry(
+            TokenType::LeftBrace,
+            PrefixEntry::Block(BlockParselet::new()),
+        );
+        grammar.register_prefix_entry(TokenType::Match, PrefixEntry::Match(MatchParselet::new()));
+        grammar.register_infix_entry(
+            TokenType::Pipe,
+            InfixEntry::Pipeline(PipelineParselet::new()),
+        );
+        grammar.register_infix_entry(TokenType::Assign, InfixEntry::Assign(AssignParselet::new()));
+        grammar.register_infix_entry(
+            TokenType::Question,
+            InfixEntry::Conditional(ConditionalParselet::new()),
+        );
+        grammar.register_prefix_entry(
+            TokenType::LeftParen,
+            PrefixEntry::Group(GroupParselet::new()),
+        );
+        grammar.register_infix_entry(TokenType::LeftParen, InfixEntry::Call(CallParselet::new()));
+        grammar.register_infix_entry(
+            TokenType::QuestionDot,
+            InfixEntry::OptionalMember(OptionalMemberParselet::new()),
+        );
+
+        // Register the simple operator parselets
+        grammar.prefix(TokenType::Plus, Precedence::Prefix);
+        grammar.prefix(TokenType::Minus, Precedence::Prefix);
+        grammar.prefix(TokenType::Tilde, Precedence::Prefix);
+        grammar.prefix(TokenType::Bang, Precedence::Prefix);
+
+        // For kicks, we'll make "!" both prefix and postfix, kinda like ++
+        grammar.postfix(TokenType::Bang, Precedence::Postfix);
+
+        grammar.infix_right(TokenType::QuestionQuestion, Precedence::Coalesce);
+        grammar.infix_left(TokenType::DotDot, Precedence::Range);
+        grammar.infix_left(TokenType::DotDotEqual, Precedence::Range);
+        grammar.infix_left(TokenType::Plus, Precedence::Sum);
+        grammar.infix_left(TokenType::Minus, Precedence::Sum);
+        grammar.infix_left(TokenType::Asterisk, Precedence::Product);
+        grammar.infix_left(TokenType::Slash, Precedence::Product);
+        grammar.infix_right(TokenType::Caret, Precedence::Exponent);
+
+        grammar
+    }
+
+    // A parser for one input that shares this grammar's tables
+    pub fn parser<'src>(
+        &self,
+        tokens: impl IntoIterator<Item = Token<'src>, IntoIter: Send + 'src>,
+    ) -> Parser<'src, B>
+    where
+        B: Default,
+    {
+        Parser::with_grammar(self.clone(), tokens)
+    }
+
+    // The same with a builder that has been set up already
+    pub fn parser_with_builder<'src>(
+        &self,
+        builder: B,
+        tokens: impl IntoIterator<Item = Token<'src>, IntoIter: Send + 'src>,
+    ) -> Parser<'src, B> {
+        Parser::with_builder(self.clone(), builder, tokens)
+    }
+
+    // Lexes and parses a single expression, including any operator
+    // declarations in front of it. Declarations only last for this input
+    pub fn parse(&self, input: &str) -> B::Output
+    where
+        B: Default,
+    {
+        let lexer = Lexer::new(input);
+        BantamParser::with_grammar(self.clone(), lexer).parse_expression()
+    }
+
+    // Parses every input on its own, spread over as many threads as the
+    // machine has cores. The results are in the same order as the inputs. If
+    // one input fails to parse, the whole batch panics with its error
+    pub fn parse_batch(&self, inputs: &[&str]) -> Vec<B::Output>
+    where
+        B: Default,
+        B::Output: Send,
+    {
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let chunk_size = inputs.len().div_ceil(threads).max(1);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = inputs
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|input| self.parse(input))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect()
+        })
+    }
+
+    // Parselets registered here are always called through `dyn`, see
+    // PrefixEntry
+    pub fn register_prefix(&mut self, tt: TokenType, parselet: Box<dyn PrefixParselet<B>>) {
+        self.register_prefix_entry(tt, PrefixEntry::Custom(Arc::from(parselet)));
+    }
+
+    pub fn register_infix(&mut self, tt: TokenType, parselet: Box<dyn InfixParselet<B>>) {
+        self.register_infix_entry(tt, InfixEntry::Custom(Arc::from(parselet)));
+    }
+
+    pub fn register_prefix_entry(&mut self, tt: TokenType, entry: PrefixEntry<B>) {
+        let tables = self.tables_mut();
+        match tt.index() {
+            Some(i) => tables.prefix_parselets[i] = Some(entry),
+            None => {
+                tables.operator_prefix_parselets.insert(tt, entry);
+            }
+        }
+    }
+
+    pub fn register_infix_entry(&mut self, tt: TokenType, entry: InfixEntry<B>) {
+        let tables = self.tables_mut();
+        match tt.index() {
+            Some(i) => {
+                tables.binding_powers[i] = entry.left_binding_power();
+                tables.infix_parselets[i] = Some(entry);
+            }
+            None => {
+                tables.operator_infix_parselets.insert(tt, entry);
+            }
+        }
+    }
+
+    // The juxtaposition parselet is called like any other infix parselet,
+    // except that the token it gets is the start of the right operand and has
+    // not been consumed yet
+    pub fn register_juxtaposition(&mut self, parselet: Box<dyn InfixParselet<B>>) {
+        self.tables_mut().juxtaposition = Some(Arc::from(parselet));
+    }
+
+    /// Register a prefix unary operator parselet for the given token and precedence
+    pub fn prefix(&mut self, tt: TokenType, precedence: Precedence) {
+        let parselet = PrefixOperatorParselet::new(precedence);
+        self.register_prefix_entry(tt, PrefixEntry::Operator(parselet));
+    }
+
+    /// Register a postfix unary operator parselet for the given token and precedence
+    pub fn postfix(&mut self, tt: TokenType, precedence: Precedence) {
+        let parselet = PostfixOperatorParselet::new(precedence);
+        self.register_infix_entry(tt, InfixEntry::Postfix(parselet));
+    }
+
+    /// Register a left-associative binary operator parselet for the given token and precedence
+    pub fn infix_left(&mut self, tt: TokenType, precedence: Precedence) {
+        let parselet = BinaryOperatorParselet::new(precedence, false);
+        self.register_infix_entry(tt, InfixEntry::Binary(parselet));
+    }
+
+    /// Register a right-associative binary operator parselet for the given token and precedence
+    pub fn infix_right(&mut self, tt: TokenType, precedence: Precedence) {
+        let parselet = BinaryOperatorParselet::new(precedence, true);
+        self.register_infix_entry(tt, InfixEntry::Binary(parselet));
+    }
+
+    /// Register a prefix unary operator parselet with the binding power of its operand
+    pub fn prefix_binding_power(&mut self, tt: TokenType, right_bp: u8) {
+        let parselet = PrefixOperatorParselet::with_binding_power(right_bp);
+        self.register_prefix_entry(tt, PrefixEntry::Operator(parselet));
+    }
+
+    /// Register a postfix unary operator parselet with the binding power of its operand
+    pub fn postfix_binding_power(&mut self, tt: TokenType, left_bp: u8) {
+        let parselet = PostfixOperatorParselet::with_binding_power(left_bp);
+        self.register_infix_entry(tt, InfixEntry::Postfix(parselet));
+    }
+
+    /// Register a binary operator parselet with a (left, right) binding power pair
+    pub fn infix_binding_power(&mut self, tt: TokenType, left_bp: u8, right_bp: u8) {
+        let parselet = BinaryOperatorParselet::with_binding_power(left_bp, right_bp);
+        self.register_infix_entry(tt, InfixEntry::Binary(parselet));
+    }
+
+    pub fn get_prefix(&self, tt: TokenType) -> Option<&PrefixEntry<B>> {
+        match tt.index() {
+            Some(i) => self.tables.prefix_parselets[i].as_ref(),
+            None => self.tables.operator_prefix_parselets.get(&tt),
+        }
+    }
+
+    pub fn get_infix(&self, tt: TokenType) -> Option<&InfixEntry<B>> {
+        match tt.index() {
+            Some(i) => self.tables.infix_parselets[i].as_ref(),
+            None => self.tables.operator_infix_parselets.get(&tt),
+        }
+    }
+
+    // The left binding power of the infix parselet for `tt`, or 0 if there is none
+    pub fn get_binding_power(&self, tt: TokenType) -> u8 {
+        match tt.index() {
+            Some(i) => self.tables.binding_powers[i],
+            None => self
+                .tables
+                .operator_infix_parselets
+                .get(&tt)
+                .map_or(0, |infix| infix.left_binding_power()),
+        }
+    }
+
+    pub fn get_juxtaposition(&self) -> Option<&Arc<dyn InfixParselet<B>>> {
+        self.tables.juxtaposition.as_ref()
+    }
+
+    // Copies the tables first if another Grammar or Parser is still using them
+    fn tables_mut(&mut self) -> &mut Tables<B> {
+        Arc::make_mut(&mut self.tables)
+    }
+}
@@ -0,0 +1,422 @@
+// Expressions as JSON. Every node is an object whose "kind" says what it is,
+// followed by the fields of the matching Expr variant:
+//
+//   {"kind":"name","name":"a"}
+//   {"kind":"number","value":"1"}
+//   {"kind":"prefix","operator":"-","right":<node>}
+//   {"kind":"binary","left":<node>,"operator":"+","right":<node>}
+//   {"kind":"postfix","left":<node>,"operator":"!"}
+//   {"kind":"conditional","condition":<node>,"then_arm":<node>,"else_arm":<node>}
+//   {"kind":"assign","name":"a","right":<node>}
+//   {"kind":"call","function":<node>,"args":[<node>, ...]}
+//   {"kind":"tuple","elements":[<node>, ...]}
+//   {"kind":"lambda","params":["a", ...],"body":<node>}
+//   {"kind":"section","left":<node or null>,"operator":"*","right":<node or null>}
+//   {"kind":"optional_member","object":<node>,"name":"b"}
+//   {"kind":"optional_call","function":<node>,"args":[<node>, ...]}
+//   {"kind":"pipeline","value":<node>,"function":<node>}
+//   {"kind":"match","value":<node>,"arms":[{"pattern":<pattern>,"body":<node>}, ...]}
+//   {"kind":"let","name":"x","value":<node>,"body":<node>}
+//   {"kind":"block","statements":[<node>, ...]}
+//
+// and patterns are one of
+//
+//   {"kind":"number","value":"0"}
+//   {"kind":"name","name":"x"}
+//   {"kind":"wildcard"}
+//   {"kind":"tuple","elements":[<pattern>, ...]}
+//
+// Operators are written as their symbol, declared ones like "<+>" included,
+// and numbers as the text they were written with. Only symbols that can be
+// the operator of a prefix, binary or postfix expression are read back, so
+// brackets and separators like "(" or "->" are rejected.
+//
+// Any node may also end with a "span":{"start":0,"end":1}, the byte offsets
+// of the source it was parsed from. Only an Expr from the AstBuilder knows
+// its spans. The Expression trees don't, so they lose them when read.
+//
+// to_json() is always there. With the `serde` feature Expr, Pattern and the
+// Expression trees implement Serialize and Deserialize in the same format,
+// which is also the only way to read it back in.
+
+use crate::{
+    core::TokenType,
+    expression::{Expr, Expression},
+    pattern::Pattern,
+};
+
+// Writes the same compact JSON serde_json would
+pub fn to_json(expression: &dyn Expression) -> String {
+    let mut json = String::new();
+    match expression.as_any().downcast_ref::<Expr>() {
+        Some(expr) => write_expr(expr, &mut json),
+        None => write_expr(&Expr::from(expression), &mut json),
+    }
+    json
+}
+
+fn write_expr(expr: &Expr, json: &mut String) {
+    let list = |exprs: &[Expr], json: &mut String| {
+        write_list(exprs, json, write_expr);
+    };
+
+    match expr {
+        Expr::Name { name, .. } => {
+            begin("name", json);
+            key("name", json);
+            write_str(name, json);
+        }
+        Expr::Number { value, .. } => {
+            begin("number", json);
+            key("value", json);
+            write_str(value, json);
+        }
+        Expr::Prefix {
+            operator, right, ..
+        } => {
+            begin("prefix", json);
+            key("operator", json);
+            write_operator(*operator, json);
+            key("right", json);
+            write_expr(right, json);
+        }
+        Expr::Binary {
+            left,
+            operator,
+            right,
+            ..
+        } => {
+            begin("binary", json);
+            key("left", json);
+            write_expr(left, json);
+            key("operator", json);
+            write_operator(*operator, json);
+            key("right", json);
+            write_expr(right, json);
+        }
+        Expr::Postfix { left, operator, .. } => {
+            begin("postfix", json);
+            key("left", json);
+            write_expr(left, json);
+            key("operator", json);
+            write_operator(*operator, json);
+        }
+        Expr::Conditional {
+            condition,
+            then_arm,
+            else_arm,
+            ..
+        } => {
+            begin("conditional", json);
+            key("condition", json);
+            write_expr(condition, json);
+            key("then_arm", json);
+            write_expr(then_arm, json);
+            key("else_arm", json);
+            write_expr(else_arm, json);
+        }
+        Expr::Assign { name, right, .. } => {
+            begin("assign", json);
+            key("name", json);
+            write_str(name, json);
+            key("right", json);
+            write_expr(right, json);
+        }
+        Expr::Call { function, args, .. } => {
+            begin("call", json);
+            key("function", json);
+            write_expr(function, json);
+            key("args", json);
+            list(args, json);
+        }
+        Expr::Tuple { elements, .. } => {
+            begin("tuple", json);
+            key("elements", json);
+            list(elements, json);
+        }
+        Expr::Lambda { params, body, .. } => {
+            begin("lambda", json);
+            key("params", json);
+            write_list(params, json, |param, json| write_str(param, json));
+            key("body", json);
+            write_expr(body, json);
+        }
+        Expr::Section {
+            left,
+            operator,
+            right,
+            ..
+        } => {
+            begin("section", json);
+            key("left", json);
+            write_optional(left.as_deref(), json);
+            key("operator", json);
+            write_operator(*operator, json);
+            key("right", json);
+            write_optional(right.as_deref(), json);
+        }
+        Expr::OptionalMember { object, name, .. } => {
+            begin("optional_member", json);
+            key("object", json);
+            write_expr(object, json);
+            key("name", json);
+            write_str(name, json);
+        }
+        Expr::OptionalCall { function, args, .. } => {
+            begin("optional_call", json);
+            key("function", json);
+            write_expr(function, json);
+            key("args", json);
+            list(args, json);
+        }
+        Expr::Pipeline {
+            value, function, ..
+        } => {
+            begin("pipeline", json);
+            key("value", json);
+            write_expr(value, json);
+            key("function", json);
+            write_expr(function, json);
+        }
+        Expr::Match { value, arms, .. } => {
+            begin("match", json);
+            key("value", json);
+            write_expr(value, json);
+            key("arms", json);
+            write_list(arms, json, |(pattern, body), json| {
+                json.push_str("{\"pattern\":");
+                write_pattern(pattern, json);
+                json.push_str(",\"body\":");
+                write_expr(body, json);
+                json.push('}');
+            });
+        }
+        Expr::Let {
+            name, value, body, ..
+        } => {
+            begin("let", json);
+            key("name", json);
+            write_str(name, json);
+            key("value", json);
+            write_expr(value, json);
+            key("body", json);
+            write_expr(body, json);
+        }
+        Expr::Block { statements, .. } => {
+            begin("block", json);
+            key("statements", json);
+            list(statements, json);
+        }
+    }
+    if let Some(span) = expr.span() {
+        key("span", json);
+        json.push_str(&format!(
+            "{{\"start\":{},\"end\":{}}}",
+            span.start, span.end
+        ));
+    }
+    json.push('}');
+}
+
+fn write_pattern(pattern: &Pattern, json: &mut String) {
+    match pattern {
+        Pattern::Number(value) => {
+            begin("number", json);
+            key("value", json);
+            write_str(value, json);
+        }
+        Pattern::Name(name) => {
+            begin("name", json);
+            key("name", json);
+            write_str(name, json);
+        }
+        Pattern::Wildcard => begin("wildcard", json),
+        Pattern::Tuple(elements) => {
+            begin("tuple", json);
+            key("elements", json);
+            write_list(elements, json, write_pattern);
+        }
+    }
+    json.push('}');
+}
+
+// Opens a node's object, the caller closes it
+fn begin(kind: &str, json: &mut String) {
+    json.push_str("{\"kind\":");
+    write_str(kind, json);
+}
+
+fn key(key: &str, json: &mut String) {
+    json.push(',');
+    write_str(key, json);
+    json.push(':');
+}
+
+fn write_optional(expr: Option<&Expr>, json: &mut String) {
+    match expr {
+        Some(expr) => write_expr(expr, json),
+        None => json.push_str("null"),
+    }
+}
+
+fn write_list<T>(items: &[T], json: &mut String, write: impl Fn(&T, &mut String)) {
+    json.push('[');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write(item, json);
+    }
+    json.push(']');
+}
+
+fn write_operator(operator: TokenType, json: &mut String) {
+    match operator.punctuator() {
+        Some(symbol) => write_str(symbol, json),
+        None => panic!("{operator} is not an operator."),
+    }
+}
+
+fn write_str(text: &str, json: &mut String) {
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            '\u{8}' => json.push_str("\\b"),
+            '\u{c}' => json.push_str("\\f"),
+            c if c.is_control() && (c as u32) < 0x20 => {
+                json.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::expression::{Expr, Expression};
+
+    impl Serialize for dyn Expression {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self.as_any().downcast_ref::<Expr>() {
+                Some(expr) => expr.serialize(serializer),
+                None => Expr::from(self).serialize(serializer),
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Box<dyn Expression> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Expr::deserialize(deserializer).map(Box::from)
+        }
+    }
+}
+
+// Operators are their symbol instead of the TokenType variant
+#[cfg(feature = "serde")]
+pub(crate) mod operator {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::core::TokenType;
+
+    pub fn serialize<S: Serializer>(
+        operator: &TokenType,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match operator.punctuator() {
+            Some(symbol) => serializer.serialize_str(symbol),
+            None => Err(serde::ser::Error::custom(format!(
+                "{operator} is not an operator"
+            ))),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TokenType, D::Error> {
+        let symbol = String::deserialize(deserializer)?;
+        TokenType::from_operator(&symbol)
+            .ok_or_else(|| D::Error::custom(format!("{symbol:?} is not an operator")))
+    }
+}
+
+// Match arms are {"pattern","body"} objects instead of pairs
+#[cfg(feature = "serde")]
+pub(crate) mod arms {
+    use serde::{ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::{expression::Expr, pattern::Pattern};
+
+    #[derive(Serialize)]
+    struct ArmRef<'a> {
+        pattern: &'a Pattern,
+        body: &'a Expr,
+    }
+
+    #[derive(Deserialize)]
+    struct Arm {
+        pattern: Pattern,
+        body: Expr,
+    }
+
+    pub fn serialize<S: Serializer>(
+        arms: &[(Pattern, Expr)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(arms.len()))?;
+        for (pattern, body) in arms {
+            seq.serialize_element(&ArmRef { pattern, body })?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(Pattern, Expr)>, D::Error> {
+        let arms = Vec::<Arm>::deserialize(deserializer)?;
+        Ok(arms
+            .into_iter()
+            .map(|arm| (arm.pattern, arm.body))
+            .collect())
+    }
+}
+
+// Pattern's tuple variants can't take field names, so serde goes through this
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum PatternRepr {
+    Number { value: String },
+    Name { name: String },
+    Wildcard,
+    Tuple { elements: Vec<Pattern> },
+}
+
+#[cfg(feature = "serde")]
+impl From<PatternRepr> for Pattern {
+    fn from(repr: PatternRepr) -> Self {
+        match repr {
+            PatternRepr::Number { value } => Pattern::Number(value),
+            PatternRepr::Name { name } => Pattern::Name(name),
+            PatternRepr::Wildcard => Pattern::Wildcard,
+            PatternRepr::Tuple { elements } => Pattern::Tuple(elements),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Pattern> for PatternRepr {
+    fn from(pattern: Pattern) -> Self {
+        match pattern {
+            Pattern::Number(value) => PatternRepr::Number { value },
+            Pattern::Name(name) => PatternRepr::Name { name },
+            Pattern::Wildcard => PatternRepr::Wildcard,
+            Pattern::Tuple(elements) => PatternRepr::Tuple { elements },
+        }
+    }
+}
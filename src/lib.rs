@@ -1,3 +1,9 @@
+pub mod arena;
+pub mod builder;
 pub mod core;
 pub mod expression;
+pub mod grammar;
+pub mod json;
 pub mod parselet;
+pub mod pattern;
+pub mod visit;
@@ -1,8 +1,8 @@
-use crate::core::{Parser, Precedence, Token, TokenType};
-use crate::expression::{
-    AssignExpression, CallExpression, ConditionalExpression, Expression, NameExpression,
-    OperatorExpression, PostfixExpression, PrefixExpression,
-};
+use std::sync::Arc;
+
+use crate::builder::{ExprBuilder, TreeBuilder};
+use crate::core::{Parser, Precedence, Restrictions, Token, TokenType};
+use crate::pattern::Pattern;
 
 // One of the two interfaces used by the Pratt parser. A PrefixParselet is
 // associated with a token that appears at the beginning of an expression. Its
@@ -11,21 +11,72 @@ use crate::expression::{
 // This interface is also used for single-token expressions like variables, in
 // which case parse() simply doesn't consume any more tokens.
 // @author rnystrom
-pub trait PrefixParselet {
-    fn parse(&self, parser: &mut Parser, token: Token) -> Box<dyn Expression>;
+//
+// Parselets are generic over the builder that turns what they parse into the
+// parser's output, see ExprBuilder. The built-in ones work with any builder
+pub trait PrefixParselet<B: ExprBuilder = TreeBuilder>: Send + Sync {
+    fn parse(&self, parser: &mut Parser<B>, token: Token) -> B::Output;
+
+    // Only for parselets that do nothing but apply an operator, see Fixity
+    fn fixity(&self) -> Option<Fixity> {
+        None
+    }
+}
+
+// What a parselet does if all it does is apply an operator to its operands.
+// Parser::parse_expression_iterative() uses it to parse those operands
+// itself instead of calling parse(), which would recurse. A parselet that
+// returns a Fixity has to build the same thing from the operator token as the
+// built-in ones do, which is whatever the builder's prefix(), binary() or
+// postfix() makes of it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fixity {
+    Prefix { right_binding_power: u8 },
+    Infix { right_binding_power: u8 },
+    Postfix,
 }
 
-// Bantam has one single-token expression: named variables
+// Bantam has two single-token expressions: named variables and numbers
+#[derive(Default, Clone)]
 pub struct NameParselet {}
 
+#[derive(Default, Clone)]
+pub struct NumberParselet {}
+
 // We can use a single struct for all the prefix operators since they only differ in the actual operator token itself
+#[derive(Clone)]
 pub struct PrefixOperatorParselet {
-    precedence: Precedence,
+    right_binding_power: u8,
 }
 
-// Parses Parentheses used to group an expression `a * (b + c)`
+// Parses Parentheses used to group an expression `a * (b + c)`. A lot of other
+// things start with `(` too, and the parselet has to tell them apart:
+//
+//   (a, b) -> a + b   a lambda, found by speculatively reading a parameter list
+//   (a, b), (a,), ()  a tuple, when the first expression is followed by `,`
+//   (+), (* 2), (2 *) an operator section, where a binary operator is missing
+//                     one or both of its operands
+//
+// A binary operator right after `(` always starts a section, even if it has
+// a prefix parselet too, so `(+ 1)` is a section. The one exception is `-`,
+// like in Haskell: `(- 1)` is a negation. Like in Haskell the operand of a
+// section has to bind tighter than its operator, so `(a + b *)` and
+// `(* a + b)` are errors rather than `((a + b) *)` and `(* (a + b))`
+#[derive(Default, Clone)]
 pub struct GroupParselet {}
 
+// let x = a + b in x * x
+// The binding is parsed as an ordinary expression, so it goes through the
+// AssignParselet. It's up to the builder to check that it came back as an
+// assignment
+#[derive(Default, Clone)]
+pub struct LetParselet {}
+
+// { a = 1; b = a + 2; b * 2 }
+// A sequence of expressions separated by `;`. A trailing `;` is allowed
+#[derive(Default, Clone)]
+pub struct BlockParselet {}
+
 impl NameParselet {
     pub fn new() -> Self {
         Self {}
@@ -33,22 +84,47 @@ impl NameParselet {
 }
 
 // Parselet implementation to parse variables names
-impl PrefixParselet for NameParselet {
-    fn parse(&self, _parser: &mut Parser, token: Token) -> Box<dyn Expression> {
-        Box::new(NameExpression::new(token.text))
+impl<B: ExprBuilder> PrefixParselet<B> for NameParselet {
+    fn parse(&self, parser: &mut Parser<B>, token: Token) -> B::Output {
+        parser.builder().name(token.get_text())
+    }
+}
+
+impl NumberParselet {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<B: ExprBuilder> PrefixParselet<B> for NumberParselet {
+    fn parse(&self, parser: &mut Parser<B>, token: Token) -> B::Output {
+        parser.builder().number(token.get_text())
     }
 }
 
 impl PrefixOperatorParselet {
+    // The operand takes every operator above the given precedence level
     pub fn new(precedence: Precedence) -> Self {
-        Self { precedence }
+        Self::with_binding_power(precedence.binding_power() + 1)
+    }
+
+    pub fn with_binding_power(right_binding_power: u8) -> Self {
+        Self {
+            right_binding_power,
+        }
     }
 }
 
-impl PrefixParselet for PrefixOperatorParselet {
-    fn parse(&self, parser: &mut Parser, token: Token) -> Box<dyn Expression> {
-        let operand = parser.parse_expression_precedence(self.precedence.clone());
-        return Box::new(PrefixExpression::new(*token.get_type(), operand));
+impl<B: ExprBuilder> PrefixParselet<B> for PrefixOperatorParselet {
+    fn parse(&self, parser: &mut Parser<B>, token: Token) -> B::Output {
+        let operand = parser.parse_expression_binding_power(self.right_binding_power);
+        parser.builder().prefix(*token.get_type(), operand)
+    }
+
+    fn fixity(&self) -> Option<Fixity> {
+        Some(Fixity::Prefix {
+            right_binding_power: self.right_binding_power,
+        })
     }
 }
 
@@ -56,97 +132,421 @@ impl GroupParselet {
     pub fn new() -> Self {
         Self {}
     }
+
+    // Reads `a, b) ->` and returns the parameter names, or None without
+    // panicking if the input turns out not to be a lambda
+    fn parse_parameters<B: ExprBuilder>(parser: &mut Parser<B>) -> Option<Vec<String>> {
+        let mut params = Vec::new();
+
+        if !parser.match_tok(TokenType::RightParen) {
+            loop {
+                if *parser.peek(0).get_type() != TokenType::Name {
+                    return None;
+                }
+                params.push(parser.consume().text.into_owned());
+
+                if !parser.match_tok(TokenType::Comma) {
+                    break;
+                }
+            }
+
+            if !parser.match_tok(TokenType::RightParen) {
+                return None;
+            }
+        }
+
+        parser.match_tok(TokenType::Arrow).then_some(params)
+    }
+
+    // How many tokens ahead the `)` that closes the group is, or None if the
+    // input ends first. Looks ahead without consuming anything
+    fn closing_paren<B: ExprBuilder>(parser: &mut Parser<B>) -> Option<usize> {
+        let mut depth = 0;
+        for distance in 0.. {
+            match parser.peek(distance).get_type() {
+                TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen if depth == 0 => return Some(distance),
+                TokenType::RightParen | TokenType::RightBrace if depth > 0 => depth -= 1,
+                TokenType::RightBrace | TokenType::EOF => return None,
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn section_error(operator: TokenType) -> ! {
+        panic!(
+            "The operand of the section ({}) has to bind tighter than {0}, wrap it in parentheses",
+            operator.punctuator().unwrap()
+        )
+    }
+}
+
+impl<B: ExprBuilder> PrefixParselet<B> for GroupParselet {
+    fn parse(&self, parser: &mut Parser<B>, _token: Token) -> B::Output {
+        let close = Self::closing_paren(parser);
+        let next = close.map(|close| *parser.peek(close + 1).get_type());
+
+        // Parentheses lift any restrictions from the surrounding context,
+        // with two exceptions. The condition of `(a = b) ? c : d` can't be an
+        // assignment, which is only found out here, since by the time the
+        // ConditionalParselet sees `?` the condition has been parsed. And the
+        // inner group of `((a = b)) ? c : d` keeps the restrictions of the
+        // outer one
+        if next == Some(TokenType::RightParen) {
+            return self.parse_group(parser, close);
+        }
+        if next == Some(TokenType::Question) && !parser.is_optional_call(close.unwrap() + 2) {
+            return parser.with_context(
+                Restrictions::NO_ASSIGNMENT,
+                "the condition of a conditional expression",
+                |parser| self.parse_group(parser, close),
+            );
+        }
+
+        parser.with_context(Restrictions::NONE, "parentheses", |parser| {
+            self.parse_group(parser, close)
+        })
+    }
 }
 
-impl PrefixParselet for GroupParselet {
-    fn parse(&self, parser: &mut Parser, _token: Token) -> Box<dyn Expression> {
+impl GroupParselet {
+    // `close` is where closing_paren() found the end of the group
+    fn parse_group<B: ExprBuilder>(
+        &self,
+        parser: &mut Parser<B>,
+        close: Option<usize>,
+    ) -> B::Output {
+        if let Some(params) = parser.try_parse(Self::parse_parameters) {
+            let body = parser.parse_expression();
+            return parser.builder().lambda(params, body);
+        }
+
+        if parser.match_tok(TokenType::RightParen) {
+            return parser.builder().tuple(Vec::new());
+        }
+
+        let tok_type = *parser.peek(0).get_type();
+        if parser.is_binary_operator(tok_type) {
+            if *parser.peek(1).get_type() == TokenType::RightParen {
+                // (+)
+                parser.consume();
+                parser.consume_expected(TokenType::RightParen);
+                return parser.builder().section(None, tok_type, None);
+            }
+
+            if tok_type != TokenType::Minus {
+                // (* 2)
+                parser.consume();
+                let right_bp = match parser.grammar().get_infix(tok_type).unwrap().fixity() {
+                    Some(Fixity::Infix {
+                        right_binding_power,
+                    }) => right_binding_power,
+                    _ => 0,
+                };
+                let right = parser.parse_expression_binding_power(right_bp);
+                if *parser.peek(0).get_type() != TokenType::RightParen {
+                    Self::section_error(tok_type);
+                }
+                parser.consume();
+                return parser.builder().section(None, tok_type, Some(right));
+            }
+        }
+
+        let last = close
+            .filter(|&close| close > 0)
+            .map(|close| *parser.peek(close - 1).get_type());
+        if let Some(operator) = last.filter(|&tt| parser.is_binary_operator(tt)) {
+            // (2 *)
+            // Parsing the operand at the operator's own binding power stops
+            // right before it, unless something that binds looser comes first
+            let left_bp = parser.grammar().get_binding_power(operator);
+            let left = parser.parse_expression_binding_power(left_bp);
+            if *parser.peek(0).get_type() != operator
+                || *parser.peek(1).get_type() != TokenType::RightParen
+            {
+                Self::section_error(operator);
+            }
+            parser.consume();
+            parser.consume();
+            return parser.builder().section(Some(left), operator, None);
+        }
+
         let expr = parser.parse_expression();
+        if parser.match_tok(TokenType::Comma) {
+            let mut elements = vec![expr];
+            while !parser.match_tok(TokenType::RightParen) {
+                elements.push(parser.parse_expression());
+
+                if !parser.match_tok(TokenType::Comma) {
+                    parser.consume_expected(TokenType::RightParen);
+                    break;
+                }
+            }
+
+            return parser.builder().tuple(elements);
+        }
+
         parser.consume_expected(TokenType::RightParen);
         expr
     }
 }
 
+impl LetParselet {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<B: ExprBuilder> PrefixParselet<B> for LetParselet {
+    fn parse(&self, parser: &mut Parser<B>, _token: Token) -> B::Output {
+        let binding = parser.parse_expression();
+        parser.consume_expected(TokenType::In);
+        let body = parser.parse_expression();
+        parser.builder().let_in(binding, body)
+    }
+}
+
+impl BlockParselet {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<B: ExprBuilder> PrefixParselet<B> for BlockParselet {
+    fn parse(&self, parser: &mut Parser<B>, _token: Token) -> B::Output {
+        if let Some(context) = parser.restricted(Restrictions::NO_BLOCK) {
+            panic!("a block is not allowed in {context}, wrap it in parentheses");
+        }
+
+        parser.push_context(Restrictions::NONE, "a block");
+        let mut statements = Vec::new();
+
+        while !parser.match_tok(TokenType::RightBrace) {
+            statements.push(parser.parse_expression());
+
+            if !parser.match_tok(TokenType::Semicolon) {
+                parser.consume_expected(TokenType::RightBrace);
+                break;
+            }
+        }
+        parser.pop_context();
+
+        parser.builder().block(statements)
+    }
+}
+
+// match x { 0 -> a, (y, _) -> y, _ -> c }
+// Arms are separated by `,` and a trailing `,` is allowed
+#[derive(Default, Clone)]
+pub struct MatchParselet {}
+
+impl MatchParselet {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    // The pattern sub-grammar is small enough that it doesn't need parselets
+    // of its own
+    fn parse_pattern<B: ExprBuilder>(&self, parser: &mut Parser<B>) -> Pattern {
+        let token = parser.consume();
+        match token.get_type() {
+            TokenType::Number => Pattern::Number(token.text.into_owned()),
+            TokenType::Name => Pattern::Name(token.text.into_owned()),
+            TokenType::Underscore => Pattern::Wildcard,
+            TokenType::LeftParen => {
+                let mut elements = Vec::new();
+                let mut trailing_comma = false;
+
+                while !parser.match_tok(TokenType::RightParen) {
+                    elements.push(self.parse_pattern(parser));
+
+                    trailing_comma = parser.match_tok(TokenType::Comma);
+                    if !trailing_comma {
+                        parser.consume_expected(TokenType::RightParen);
+                        break;
+                    }
+                }
+
+                // A single pattern without a comma is just grouped
+                if elements.len() == 1 && !trailing_comma {
+                    elements.remove(0)
+                } else {
+                    Pattern::Tuple(elements)
+                }
+            }
+            _ => panic!("Could not parse pattern {}.", token.get_text()),
+        }
+    }
+}
+
+impl<B: ExprBuilder> PrefixParselet<B> for MatchParselet {
+    fn parse(&self, parser: &mut Parser<B>, _token: Token) -> B::Output {
+        let value = parser.with_context(
+            Restrictions::NO_BLOCK | Restrictions::NO_ASSIGNMENT,
+            "the head of a match",
+            |parser| parser.parse_expression(),
+        );
+        parser.consume_expected(TokenType::LeftBrace);
+
+        let mut arms = Vec::new();
+        while !parser.match_tok(TokenType::RightBrace) {
+            let pattern = self.parse_pattern(parser);
+            parser.consume_expected(TokenType::Arrow);
+            arms.push((pattern, parser.parse_expression()));
+
+            if !parser.match_tok(TokenType::Comma) {
+                parser.consume_expected(TokenType::RightBrace);
+                break;
+            }
+        }
+
+        parser.builder().match_expr(value, arms)
+    }
+}
+
 // One of the two parselet interfaces used by the Pratt parser. An
 // InfixParselet is associated with a token that appears in the middle of the
 // expression it parses. Its parse() method will be called after the left-hand
 // side has been parsed, and it in turn is responsible for parsing everything
 // that comes after the token. This is also used for postfix expressions, in
 // which case it simply doesn't consume any more tokens in its parse() call.
-pub trait InfixParselet {
-    fn parse(
-        &self,
-        parser: &mut Parser,
-        left: Box<dyn Expression>,
-        token: Token,
-    ) -> Box<dyn Expression>;
-    fn get_precedence(&self) -> Precedence;
+pub trait InfixParselet<B: ExprBuilder = TreeBuilder>: Send + Sync {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, token: Token) -> B::Output;
+    // How tightly the parselet binds to the expression on its left. The parser
+    // only hands it the left expression if this is higher than the binding
+    // power it is currently parsing at
+    fn left_binding_power(&self) -> u8;
+
+    // Binary operators can be turned into sections by the GroupParselet
+    fn is_binary_operator(&self) -> bool {
+        false
+    }
+
+    // Only for parselets that do nothing but apply an operator, see Fixity
+    fn fixity(&self) -> Option<Fixity> {
+        None
+    }
 }
 
+// Binary operators have a pair of binding powers, one for each side. A
+// left-associative operator binds a little tighter on its right, so in
+// `a - b - c` the second `-` can't take `b` away from the first one. A
+// right-associative operator binds a little looser on its right instead, so
+// its right operand takes in every operator of the same precedence level
+#[derive(Clone)]
 pub struct BinaryOperatorParselet {
-    precedence: Precedence,
-    is_right: bool,
+    left_binding_power: u8,
+    right_binding_power: u8,
 }
 
+#[derive(Clone)]
 pub struct PostfixOperatorParselet {
-    precedence: Precedence,
+    left_binding_power: u8,
 }
 
 // a ? b : c
+// Also parses the optional call a?(b, c), which only calls `a` when it is
+// present. Both start with `?` followed by `(`, see Parser::is_optional_call
+#[derive(Default, Clone)]
 pub struct ConditionalParselet {}
 
 // a = b
 // left side has to be a name
 // expressions are right-associative
 // a = b = c becomes a = (b = c)
+#[derive(Default, Clone)]
 pub struct AssignParselet {}
 
+#[derive(Default, Clone)]
 pub struct CallParselet {}
 
+// a?.b
+// Only evaluates the member access when `a` is present
+#[derive(Default, Clone)]
+pub struct OptionalMemberParselet {}
+
+// 2x, 3(a + b)
+// Juxtaposition as multiplication. Register it with
+// Parser::register_juxtaposition at the precedence it should bind with
+pub struct ImplicitMultiplicationParselet {
+    precedence: Precedence,
+}
+
+// f a b
+// Juxtaposition as function application, left-associative so `f a b` is
+// `f(a)(b)`. Register it with Parser::register_juxtaposition
+pub struct ApplicationParselet {
+    precedence: Precedence,
+}
+
+// x |> f |> g(1)
+// Left-associative, so the value flows through the functions in order
+#[derive(Default, Clone)]
+pub struct PipelineParselet {}
+
 impl BinaryOperatorParselet {
     pub fn new(precedence: Precedence, is_right: bool) -> Self {
+        let bp = precedence.binding_power();
+        if is_right {
+            Self::with_binding_power(bp, bp.saturating_sub(1))
+        } else {
+            Self::with_binding_power(bp, bp + 1)
+        }
+    }
+
+    pub fn with_binding_power(left_binding_power: u8, right_binding_power: u8) -> Self {
         Self {
-            precedence,
-            is_right,
+            left_binding_power,
+            right_binding_power,
         }
     }
 }
 
-impl InfixParselet for BinaryOperatorParselet {
-    fn parse(
-        &self,
-        parser: &mut Parser,
-        left: Box<dyn Expression>,
-        token: Token,
-    ) -> Box<dyn Expression> {
-        let op_prec = self.precedence as usize - if self.is_right { 1 } else { 0 };
-        let right = parser.parse_expression_precedence(op_prec.into());
+impl<B: ExprBuilder> InfixParselet<B> for BinaryOperatorParselet {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, token: Token) -> B::Output {
+        let right = parser.parse_expression_binding_power(self.right_binding_power);
+
+        parser.builder().binary(left, *token.get_type(), right)
+    }
+
+    fn left_binding_power(&self) -> u8 {
+        self.left_binding_power
+    }
 
-        Box::new(OperatorExpression::new(left, *token.get_type(), right))
+    fn is_binary_operator(&self) -> bool {
+        true
     }
 
-    fn get_precedence(&self) -> Precedence {
-        self.precedence
+    fn fixity(&self) -> Option<Fixity> {
+        Some(Fixity::Infix {
+            right_binding_power: self.right_binding_power,
+        })
     }
 }
 
 impl PostfixOperatorParselet {
     pub fn new(precedence: Precedence) -> Self {
-        Self { precedence }
+        Self::with_binding_power(precedence.binding_power())
+    }
+
+    pub fn with_binding_power(left_binding_power: u8) -> Self {
+        Self { left_binding_power }
     }
 }
 
-impl InfixParselet for PostfixOperatorParselet {
-    fn parse(
-        &self,
-        _parser: &mut Parser,
-        left: Box<dyn Expression>,
-        token: Token,
-    ) -> Box<dyn Expression> {
-        Box::new(PostfixExpression::new(left, *token.get_type()))
+impl<B: ExprBuilder> InfixParselet<B> for PostfixOperatorParselet {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, token: Token) -> B::Output {
+        parser.builder().postfix(left, *token.get_type())
     }
 
-    fn get_precedence(&self) -> Precedence {
-        self.precedence
+    fn left_binding_power(&self) -> u8 {
+        self.left_binding_power
+    }
+
+    fn fixity(&self) -> Option<Fixity> {
+        Some(Fixity::Postfix)
     }
 }
 
@@ -156,23 +556,28 @@ impl ConditionalParselet {
     }
 }
 
-impl InfixParselet for ConditionalParselet {
-    fn parse(
-        &self,
-        parser: &mut Parser,
-        left: Box<dyn Expression>,
-        _token: Token,
-    ) -> Box<dyn Expression> {
+impl<B: ExprBuilder> InfixParselet<B> for ConditionalParselet {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, _token: Token) -> B::Output {
+        if parser.is_optional_call(0) {
+            parser.consume_expected(TokenType::LeftParen);
+            let args = parse_arguments(parser);
+            return parser.builder().optional_call(left, args);
+        }
+
+        // `=` binds looser than `?`, so `a = b ? c : d` is `a = (b ? c : d)`
+        // and an assignment can only be the condition in parentheses. The
+        // GroupParselet forbids it there
         let then_arm = parser.parse_expression();
         parser.consume_expected(TokenType::Colon);
 
-        let else_prec = Precedence::Conditional as usize - 1;
-        let else_arm = parser.parse_expression_precedence(else_prec.into());
-        Box::new(ConditionalExpression::new(left, then_arm, else_arm))
+        // Right-associative, so the else arm can be another conditional
+        let else_bp = Precedence::Conditional.binding_power() - 1;
+        let else_arm = parser.parse_expression_binding_power(else_bp);
+        parser.builder().conditional(left, then_arm, else_arm)
     }
 
-    fn get_precedence(&self) -> Precedence {
-        Precedence::Conditional
+    fn left_binding_power(&self) -> u8 {
+        Precedence::Conditional.binding_power()
     }
 }
 
@@ -182,28 +587,42 @@ impl AssignParselet {
     }
 }
 
-impl InfixParselet for AssignParselet {
-    fn parse(
-        &self,
-        parser: &mut Parser,
-        left: Box<dyn Expression>,
-        _token: Token,
-    ) -> Box<dyn Expression> {
-        let right_prec = Precedence::Assignment as usize - 1;
-        let right = parser.parse_expression_precedence(right_prec.into());
+impl<B: ExprBuilder> InfixParselet<B> for AssignParselet {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, _token: Token) -> B::Output {
+        if let Some(context) = parser.restricted(Restrictions::NO_ASSIGNMENT) {
+            panic!("assignment is not allowed in {context}");
+        }
 
-        let left_name_expr = match left.as_any().downcast_ref::<NameExpression>() {
-            Some(ne) => ne,
-            None => panic!("left hand side of assignment must be a name"),
-        };
+        let right_bp = Precedence::Assignment.binding_power() - 1;
+        let right = parser.parse_expression_binding_power(right_bp);
+        parser.builder().assign(left, right)
+    }
 
-        let name = left_name_expr.name();
-        Box::new(AssignExpression::new(name.clone(), right))
+    fn left_binding_power(&self) -> u8 {
+        Precedence::Assignment.binding_power()
     }
+}
+
+// Parses a comma-separated argument list up to and including the closing `)`.
+// The opening `(` has already been consumed
+fn parse_arguments<B: ExprBuilder>(parser: &mut Parser<B>) -> Vec<B::Output> {
+    let mut args = Vec::new();
+
+    // Could be no args
+    if !parser.match_tok(TokenType::RightParen) {
+        parser.push_context(Restrictions::NONE, "an argument list");
+        loop {
+            args.push(parser.parse_expression());
 
-    fn get_precedence(&self) -> Precedence {
-        Precedence::Assignment
+            if !parser.match_tok(TokenType::Comma) {
+                break;
+            }
+        }
+        parser.pop_context();
+        parser.consume_expected(TokenType::RightParen);
     }
+
+    args
 }
 
 impl CallParselet {
@@ -212,31 +631,207 @@ impl CallParselet {
     }
 }
 
-impl InfixParselet for CallParselet {
-    fn parse(
-        &self,
-        parser: &mut Parser,
-        left: Box<dyn Expression>,
-        _token: Token,
-    ) -> Box<dyn Expression> {
-        let mut args = Vec::new();
+impl<B: ExprBuilder> InfixParselet<B> for CallParselet {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, _token: Token) -> B::Output {
+        let args = parse_arguments(parser);
+        parser.builder().call(left, args)
+    }
 
-        // Could be no args
-        if !parser.match_tok(TokenType::RightParen) {
-            loop {
-                args.push(parser.parse_expression());
+    fn left_binding_power(&self) -> u8 {
+        Precedence::Call.binding_power()
+    }
+}
 
-                if !parser.match_tok(TokenType::Comma) {
-                    break;
-                }
+impl OptionalMemberParselet {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<B: ExprBuilder> InfixParselet<B> for OptionalMemberParselet {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, _token: Token) -> B::Output {
+        let name = parser.consume_expected(TokenType::Name);
+        parser.builder().optional_member(left, name.get_text())
+    }
+
+    fn left_binding_power(&self) -> u8 {
+        Precedence::Call.binding_power()
+    }
+}
+
+impl PipelineParselet {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl<B: ExprBuilder> InfixParselet<B> for PipelineParselet {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, _token: Token) -> B::Output {
+        let function = parser.parse_expression_precedence(Precedence::Pipeline);
+        parser.builder().pipeline(left, function)
+    }
+
+    fn left_binding_power(&self) -> u8 {
+        Precedence::Pipeline.binding_power()
+    }
+}
+
+impl ImplicitMultiplicationParselet {
+    pub fn new(precedence: Precedence) -> Self {
+        Self { precedence }
+    }
+}
+
+impl<B: ExprBuilder> InfixParselet<B> for ImplicitMultiplicationParselet {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, _token: Token) -> B::Output {
+        let right = parser.parse_expression_precedence(self.precedence);
+        parser.builder().binary(left, TokenType::Asterisk, right)
+    }
+
+    fn left_binding_power(&self) -> u8 {
+        self.precedence.binding_power()
+    }
+}
+
+impl ApplicationParselet {
+    pub fn new(precedence: Precedence) -> Self {
+        Self { precedence }
+    }
+}
+
+impl<B: ExprBuilder> InfixParselet<B> for ApplicationParselet {
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, _token: Token) -> B::Output {
+        let arg = parser.parse_expression_precedence(self.precedence);
+        parser.builder().call(left, vec![arg])
+    }
+
+    fn left_binding_power(&self) -> u8 {
+        self.precedence.binding_power()
+    }
+}
+
+// What the grammar's prefix table holds. Going through `dyn PrefixParselet`
+// for every token costs a virtual call that can't be inlined, so the built-in
+// parselets get a variant of their own and are called directly. Anything
+// else, like a parselet from outside the crate, is a Custom one
+pub enum PrefixEntry<B: ExprBuilder = TreeBuilder> {
+    Name(NameParselet),
+    Number(NumberParselet),
+    Operator(PrefixOperatorParselet),
+    Group(GroupParselet),
+    Let(LetParselet),
+    Block(BlockParselet),
+    Match(MatchParselet),
+    Custom(Arc<dyn PrefixParselet<B>>),
+}
+
+// Evaluates `$call` with `$p` bound to whichever parselet the entry holds
+macro_rules! dispatch_prefix {
+    ($entry:expr, $p:ident => $call:expr) => {
+        match $entry {
+            PrefixEntry::Name($p) => $call,
+            PrefixEntry::Number($p) => $call,
+            PrefixEntry::Operator($p) => $call,
+            PrefixEntry::Group($p) => $call,
+            PrefixEntry::Let($p) => $call,
+            PrefixEntry::Block($p) => $call,
+            PrefixEntry::Match($p) => $call,
+            PrefixEntry::Custom(custom) => {
+                let $p = &**custom;
+                $call
             }
-            parser.consume_expected(TokenType::RightParen);
         }
+    };
+}
+
+// Cloned by hand, deriving Clone would require the builder to be Clone too
+impl<B: ExprBuilder> Clone for PrefixEntry<B> {
+    fn clone(&self) -> Self {
+        match self {
+            PrefixEntry::Name(p) => PrefixEntry::Name(p.clone()),
+            PrefixEntry::Number(p) => PrefixEntry::Number(p.clone()),
+            PrefixEntry::Operator(p) => PrefixEntry::Operator(p.clone()),
+            PrefixEntry::Group(p) => PrefixEntry::Group(p.clone()),
+            PrefixEntry::Let(p) => PrefixEntry::Let(p.clone()),
+            PrefixEntry::Block(p) => PrefixEntry::Block(p.clone()),
+            PrefixEntry::Match(p) => PrefixEntry::Match(p.clone()),
+            PrefixEntry::Custom(p) => PrefixEntry::Custom(p.clone()),
+        }
+    }
+}
+
+impl<B: ExprBuilder> PrefixParselet<B> for PrefixEntry<B> {
+    #[inline]
+    fn parse(&self, parser: &mut Parser<B>, token: Token) -> B::Output {
+        dispatch_prefix!(self, p => p.parse(parser, token))
+    }
+
+    fn fixity(&self) -> Option<Fixity> {
+        dispatch_prefix!(self, p => PrefixParselet::<B>::fixity(p))
+    }
+}
+
+// The same as PrefixEntry for the infix table
+pub enum InfixEntry<B: ExprBuilder = TreeBuilder> {
+    Binary(BinaryOperatorParselet),
+    Postfix(PostfixOperatorParselet),
+    Conditional(ConditionalParselet),
+    Assign(AssignParselet),
+    Call(CallParselet),
+    OptionalMember(OptionalMemberParselet),
+    Pipeline(PipelineParselet),
+    Custom(Arc<dyn InfixParselet<B>>),
+}
+
+macro_rules! dispatch_infix {
+    ($entry:expr, $p:ident => $call:expr) => {
+        match $entry {
+            InfixEntry::Binary($p) => $call,
+            InfixEntry::Postfix($p) => $call,
+            InfixEntry::Conditional($p) => $call,
+            InfixEntry::Assign($p) => $call,
+            InfixEntry::Call($p) => $call,
+            InfixEntry::OptionalMember($p) => $call,
+            InfixEntry::Pipeline($p) => $call,
+            InfixEntry::Custom(custom) => {
+                let $p = &**custom;
+                $call
+            }
+        }
+    };
+}
+
+impl<B: ExprBuilder> Clone for InfixEntry<B> {
+    fn clone(&self) -> Self {
+        match self {
+            InfixEntry::Binary(p) => InfixEntry::Binary(p.clone()),
+            InfixEntry::Postfix(p) => InfixEntry::Postfix(p.clone()),
+            InfixEntry::Conditional(p) => InfixEntry::Conditional(p.clone()),
+            InfixEntry::Assign(p) => InfixEntry::Assign(p.clone()),
+            InfixEntry::Call(p) => InfixEntry::Call(p.clone()),
+            InfixEntry::OptionalMember(p) => InfixEntry::OptionalMember(p.clone()),
+            InfixEntry::Pipeline(p) => InfixEntry::Pipeline(p.clone()),
+            InfixEntry::Custom(p) => InfixEntry::Custom(p.clone()),
+        }
+    }
+}
+
+impl<B: ExprBuilder> InfixParselet<B> for InfixEntry<B> {
+    #[inline]
+    fn parse(&self, parser: &mut Parser<B>, left: B::Output, token: Token) -> B::Output {
+        dispatch_infix!(self, p => p.parse(parser, left, token))
+    }
+
+    #[inline]
+    fn left_binding_power(&self) -> u8 {
+        dispatch_infix!(self, p => InfixParselet::<B>::left_binding_power(p))
+    }
 
-        Box::new(CallExpression::new(left, args))
+    fn is_binary_operator(&self) -> bool {
+        dispatch_infix!(self, p => InfixParselet::<B>::is_binary_operator(p))
     }
 
-    fn get_precedence(&self) -> Precedence {
-        Precedence::Call
+    fn fixity(&self) -> Option<Fixity> {
+        dispatch_infix!(self, p => InfixParselet::<B>::fixity(p))
     }
 }
@@ -0,0 +1,43 @@
+// Patterns are the left-hand side of a match arm. Unlike expressions they form
+// a small closed grammar, so they are a plain enum instead of a trait
+//
+// 0          a literal number, matches only that value
+// x          a name, matches anything and binds it to x
+// _          the wildcard, matches anything without binding it
+// (a, _, 1)  a tuple, matches element by element
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "crate::json::PatternRepr", into = "crate::json::PatternRepr")
+)]
+pub enum Pattern {
+    Number(String),
+    Name(String),
+    Wildcard,
+    Tuple(Vec<Pattern>),
+}
+
+impl Pattern {
+    pub fn print(&self, builder: &mut String) {
+        match self {
+            Pattern::Number(value) => builder.push_str(value),
+            Pattern::Name(name) => builder.push_str(name),
+            Pattern::Wildcard => builder.push('_'),
+            Pattern::Tuple(elements) => {
+                builder.push('(');
+                for (i, element) in elements.iter().enumerate() {
+                    element.print(builder);
+                    if i + 1 < elements.len() {
+                        builder.push_str(", ");
+                    }
+                }
+                // Without the comma a single element would read as a group
+                if elements.len() == 1 {
+                    builder.push(',');
+                }
+                builder.push(')');
+            }
+        }
+    }
+}
@@ -0,0 +1,561 @@
+use crate::expression::{
+    AssignExpression, BlockExpression, CallExpression, ConditionalExpression, Expression,
+    LambdaExpression, LetExpression, MatchArm, MatchExpression, NameExpression, NumberExpression,
+    OperatorExpression, OptionalCallExpression, OptionalMemberExpression, PipelineExpression,
+    PostfixExpression, PrefixExpression, SectionExpression, TupleExpression,
+};
+
+// Walks a tree of Expression nodes. There is a visit_ method for every kind
+// of node, and by default each one visits the node's children through the
+// walk_ function of the same name. A visitor overrides the methods for the
+// nodes it cares about and calls the walk_ function from there if it still
+// wants to go deeper.
+//
+// The walk_ functions throw away what visiting the children returns and
+// return R::default(), so a visitor that computes something has to override
+// the methods for every node it can meet
+pub trait ExpressionVisitor<R: Default = ()> {
+    // Finds out what kind of node `expression` is and calls its visit_ method
+    fn visit_expression(&mut self, expression: &dyn Expression) -> R {
+        walk_expression(self, expression)
+    }
+
+    fn visit_name(&mut self, _name: &NameExpression) -> R {
+        R::default()
+    }
+
+    fn visit_number(&mut self, _number: &NumberExpression) -> R {
+        R::default()
+    }
+
+    fn visit_prefix(&mut self, prefix: &PrefixExpression) -> R {
+        walk_prefix(self, prefix)
+    }
+
+    fn visit_operator(&mut self, operator: &OperatorExpression) -> R {
+        walk_operator(self, operator)
+    }
+
+    fn visit_postfix(&mut self, postfix: &PostfixExpression) -> R {
+        walk_postfix(self, postfix)
+    }
+
+    fn visit_conditional(&mut self, conditional: &ConditionalExpression) -> R {
+        walk_conditional(self, conditional)
+    }
+
+    fn visit_assign(&mut self, assign: &AssignExpression) -> R {
+        walk_assign(self, assign)
+    }
+
+    fn visit_call(&mut self, call: &CallExpression) -> R {
+        walk_call(self, call)
+    }
+
+    fn visit_tuple(&mut self, tuple: &TupleExpression) -> R {
+        walk_tuple(self, tuple)
+    }
+
+    fn visit_lambda(&mut self, lambda: &LambdaExpression) -> R {
+        walk_lambda(self, lambda)
+    }
+
+    fn visit_section(&mut self, section: &SectionExpression) -> R {
+        walk_section(self, section)
+    }
+
+    fn visit_optional_member(&mut self, member: &OptionalMemberExpression) -> R {
+        walk_optional_member(self, member)
+    }
+
+    fn visit_optional_call(&mut self, call: &OptionalCallExpression) -> R {
+        walk_optional_call(self, call)
+    }
+
+    fn visit_pipeline(&mut self, pipeline: &PipelineExpression) -> R {
+        walk_pipeline(self, pipeline)
+    }
+
+    fn visit_match(&mut self, match_expression: &MatchExpression) -> R {
+        walk_match(self, match_expression)
+    }
+
+    fn visit_let(&mut self, let_expression: &LetExpression) -> R {
+        walk_let(self, let_expression)
+    }
+
+    fn visit_block(&mut self, block: &BlockExpression) -> R {
+        walk_block(self, block)
+    }
+
+    // Nodes that aren't built into the crate, like ones made by a custom
+    // parselet. The visitor can't see inside them
+    fn visit_other(&mut self, _expression: &dyn Expression) -> R {
+        R::default()
+    }
+}
+
+pub fn walk_expression<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    expression: &dyn Expression,
+) -> R {
+    let any = expression.as_any();
+    if let Some(e) = any.downcast_ref::<NameExpression>() {
+        visitor.visit_name(e)
+    } else if let Some(e) = any.downcast_ref::<NumberExpression>() {
+        visitor.visit_number(e)
+    } else if let Some(e) = any.downcast_ref::<PrefixExpression>() {
+        visitor.visit_prefix(e)
+    } else if let Some(e) = any.downcast_ref::<OperatorExpression>() {
+        visitor.visit_operator(e)
+    } else if let Some(e) = any.downcast_ref::<PostfixExpression>() {
+        visitor.visit_postfix(e)
+    } else if let Some(e) = any.downcast_ref::<ConditionalExpression>() {
+        visitor.visit_conditional(e)
+    } else if let Some(e) = any.downcast_ref::<AssignExpression>() {
+        visitor.visit_assign(e)
+    } else if let Some(e) = any.downcast_ref::<CallExpression>() {
+        visitor.visit_call(e)
+    } else if let Some(e) = any.downcast_ref::<TupleExpression>() {
+        visitor.visit_tuple(e)
+    } else if let Some(e) = any.downcast_ref::<LambdaExpression>() {
+        visitor.visit_lambda(e)
+    } else if let Some(e) = any.downcast_ref::<SectionExpression>() {
+        visitor.visit_section(e)
+    } else if let Some(e) = any.downcast_ref::<OptionalMemberExpression>() {
+        visitor.visit_optional_member(e)
+    } else if let Some(e) = any.downcast_ref::<OptionalCallExpression>() {
+        visitor.visit_optional_call(e)
+    } else if let Some(e) = any.downcast_ref::<PipelineExpression>() {
+        visitor.visit_pipeline(e)
+    } else if let Some(e) = any.downcast_ref::<MatchExpression>() {
+        visitor.visit_match(e)
+    } else if let Some(e) = any.downcast_ref::<LetExpression>() {
+        visitor.visit_let(e)
+    } else if let Some(e) = any.downcast_ref::<BlockExpression>() {
+        visitor.visit_block(e)
+    } else {
+        visitor.visit_other(expression)
+    }
+}
+
+fn walk_all<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    expressions: &[Box<dyn Expression>],
+) {
+    for expression in expressions {
+        visitor.visit_expression(expression.as_ref());
+    }
+}
+
+pub fn walk_prefix<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    prefix: &PrefixExpression,
+) -> R {
+    visitor.visit_expression(prefix.right());
+    R::default()
+}
+
+pub fn walk_operator<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    operator: &OperatorExpression,
+) -> R {
+    visitor.visit_expression(operator.left());
+    visitor.visit_expression(operator.right());
+    R::default()
+}
+
+pub fn walk_postfix<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    postfix: &PostfixExpression,
+) -> R {
+    visitor.visit_expression(postfix.left());
+    R::default()
+}
+
+pub fn walk_conditional<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    conditional: &ConditionalExpression,
+) -> R {
+    visitor.visit_expression(conditional.condition());
+    visitor.visit_expression(conditional.then_arm());
+    visitor.visit_expression(conditional.else_arm());
+    R::default()
+}
+
+pub fn walk_assign<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    assign: &AssignExpression,
+) -> R {
+    visitor.visit_expression(assign.right());
+    R::default()
+}
+
+pub fn walk_call<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    call: &CallExpression,
+) -> R {
+    visitor.visit_expression(call.function());
+    walk_all(visitor, call.args());
+    R::default()
+}
+
+pub fn walk_tuple<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    tuple: &TupleExpression,
+) -> R {
+    walk_all(visitor, tuple.elements());
+    R::default()
+}
+
+pub fn walk_lambda<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    lambda: &LambdaExpression,
+) -> R {
+    visitor.visit_expression(lambda.body());
+    R::default()
+}
+
+pub fn walk_section<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    section: &SectionExpression,
+) -> R {
+    if let Some(left) = section.left() {
+        visitor.visit_expression(left);
+    }
+    if let Some(right) = section.right() {
+        visitor.visit_expression(right);
+    }
+    R::default()
+}
+
+pub fn walk_optional_member<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    member: &OptionalMemberExpression,
+) -> R {
+    visitor.visit_expression(member.object());
+    R::default()
+}
+
+pub fn walk_optional_call<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    call: &OptionalCallExpression,
+) -> R {
+    visitor.visit_expression(call.function());
+    walk_all(visitor, call.args());
+    R::default()
+}
+
+pub fn walk_pipeline<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    pipeline: &PipelineExpression,
+) -> R {
+    visitor.visit_expression(pipeline.value());
+    visitor.visit_expression(pipeline.function());
+    R::default()
+}
+
+pub fn walk_match<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    match_expression: &MatchExpression,
+) -> R {
+    visitor.visit_expression(match_expression.value());
+    for arm in match_expression.arms() {
+        visitor.visit_expression(arm.body());
+    }
+    R::default()
+}
+
+pub fn walk_let<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    let_expression: &LetExpression,
+) -> R {
+    visitor.visit_expression(let_expression.binding());
+    visitor.visit_expression(let_expression.body());
+    R::default()
+}
+
+pub fn walk_block<R: Default, V: ExpressionVisitor<R> + ?Sized>(
+    visitor: &mut V,
+    block: &BlockExpression,
+) -> R {
+    walk_all(visitor, block.statements());
+    R::default()
+}
+
+// Builds a new tree out of an old one. Every fold_ method returns the node
+// that takes the place of the one it is given, and by default that is a copy
+// with each of the children folded, made by the fold_ function of the same
+// name. A rewrite overrides the methods for the nodes it changes
+pub trait Fold {
+    fn fold_expression(&mut self, expression: &dyn Expression) -> Box<dyn Expression> {
+        fold_expression(self, expression)
+    }
+
+    fn fold_name(&mut self, name: &NameExpression) -> Box<dyn Expression> {
+        fold_name(self, name)
+    }
+
+    fn fold_number(&mut self, number: &NumberExpression) -> Box<dyn Expression> {
+        fold_number(self, number)
+    }
+
+    fn fold_prefix(&mut self, prefix: &PrefixExpression) -> Box<dyn Expression> {
+        fold_prefix(self, prefix)
+    }
+
+    fn fold_operator(&mut self, operator: &OperatorExpression) -> Box<dyn Expression> {
+        fold_operator(self, operator)
+    }
+
+    fn fold_postfix(&mut self, postfix: &PostfixExpression) -> Box<dyn Expression> {
+        fold_postfix(self, postfix)
+    }
+
+    fn fold_conditional(&mut self, conditional: &ConditionalExpression) -> Box<dyn Expression> {
+        fold_conditional(self, conditional)
+    }
+
+    fn fold_assign(&mut self, assign: &AssignExpression) -> Box<dyn Expression> {
+        fold_assign(self, assign)
+    }
+
+    fn fold_call(&mut self, call: &CallExpression) -> Box<dyn Expression> {
+        fold_call(self, call)
+    }
+
+    fn fold_tuple(&mut self, tuple: &TupleExpression) -> Box<dyn Expression> {
+        fold_tuple(self, tuple)
+    }
+
+    fn fold_lambda(&mut self, lambda: &LambdaExpression) -> Box<dyn Expression> {
+        fold_lambda(self, lambda)
+    }
+
+    fn fold_section(&mut self, section: &SectionExpression) -> Box<dyn Expression> {
+        fold_section(self, section)
+    }
+
+    fn fold_optional_member(&mut self, member: &OptionalMemberExpression) -> Box<dyn Expression> {
+        fold_optional_member(self, member)
+    }
+
+    fn fold_optional_call(&mut self, call: &OptionalCallExpression) -> Box<dyn Expression> {
+        fold_optional_call(self, call)
+    }
+
+    fn fold_pipeline(&mut self, pipeline: &PipelineExpression) -> Box<dyn Expression> {
+        fold_pipeline(self, pipeline)
+    }
+
+    fn fold_match(&mut self, match_expression: &MatchExpression) -> Box<dyn Expression> {
+        fold_match(self, match_expression)
+    }
+
+    fn fold_let(&mut self, let_expression: &LetExpression) -> Box<dyn Expression> {
+        fold_let(self, let_expression)
+    }
+
+    fn fold_block(&mut self, block: &BlockExpression) -> Box<dyn Expression> {
+        fold_block(self, block)
+    }
+
+    // Nodes that aren't built into the crate. The fold can't see inside
+    // them, so they are copied as they are
+    fn fold_other(&mut self, expression: &dyn Expression) -> Box<dyn Expression> {
+        expression.clone_box()
+    }
+}
+
+pub fn fold_expression<F: Fold + ?Sized>(
+    folder: &mut F,
+    expression: &dyn Expression,
+) -> Box<dyn Expression> {
+    let any = expression.as_any();
+    if let Some(e) = any.downcast_ref::<NameExpression>() {
+        folder.fold_name(e)
+    } else if let Some(e) = any.downcast_ref::<NumberExpression>() {
+        folder.fold_number(e)
+    } else if let Some(e) = any.downcast_ref::<PrefixExpression>() {
+        folder.fold_prefix(e)
+    } else if let Some(e) = any.downcast_ref::<OperatorExpression>() {
+        folder.fold_operator(e)
+    } else if let Some(e) = any.downcast_ref::<PostfixExpression>() {
+        folder.fold_postfix(e)
+    } else if let Some(e) = any.downcast_ref::<ConditionalExpression>() {
+        folder.fold_conditional(e)
+    } else if let Some(e) = any.downcast_ref::<AssignExpression>() {
+        folder.fold_assign(e)
+    } else if let Some(e) = any.downcast_ref::<CallExpression>() {
+        folder.fold_call(e)
+    } else if let Some(e) = any.downcast_ref::<TupleExpression>() {
+        folder.fold_tuple(e)
+    } else if let Some(e) = any.downcast_ref::<LambdaExpression>() {
+        folder.fold_lambda(e)
+    } else if let Some(e) = any.downcast_ref::<SectionExpression>() {
+        folder.fold_section(e)
+    } else if let Some(e) = any.downcast_ref::<OptionalMemberExpression>() {
+        folder.fold_optional_member(e)
+    } else if let Some(e) = any.downcast_ref::<OptionalCallExpression>() {
+        folder.fold_optional_call(e)
+    } else if let Some(e) = any.downcast_ref::<PipelineExpression>() {
+        folder.fold_pipeline(e)
+    } else if let Some(e) = any.downcast_ref::<MatchExpression>() {
+        folder.fold_match(e)
+    } else if let Some(e) = any.downcast_ref::<LetExpression>() {
+        folder.fold_let(e)
+    } else if let Some(e) = any.downcast_ref::<BlockExpression>() {
+        folder.fold_block(e)
+    } else {
+        folder.fold_other(expression)
+    }
+}
+
+fn fold_all<F: Fold + ?Sized>(
+    folder: &mut F,
+    expressions: &[Box<dyn Expression>],
+) -> Vec<Box<dyn Expression>> {
+    expressions
+        .iter()
+        .map(|expression| folder.fold_expression(expression.as_ref()))
+        .collect()
+}
+
+pub fn fold_name<F: Fold + ?Sized>(_folder: &mut F, name: &NameExpression) -> Box<dyn Expression> {
+    Box::new(NameExpression::new(name.name().clone()))
+}
+
+pub fn fold_number<F: Fold + ?Sized>(
+    _folder: &mut F,
+    number: &NumberExpression,
+) -> Box<dyn Expression> {
+    Box::new(NumberExpression::new(number.value().clone()))
+}
+
+pub fn fold_prefix<F: Fold + ?Sized>(
+    folder: &mut F,
+    prefix: &PrefixExpression,
+) -> Box<dyn Expression> {
+    let right = folder.fold_expression(prefix.right());
+    Box::new(PrefixExpression::new(prefix.operator(), right))
+}
+
+pub fn fold_operator<F: Fold + ?Sized>(
+    folder: &mut F,
+    operator: &OperatorExpression,
+) -> Box<dyn Expression> {
+    let left = folder.fold_expression(operator.left());
+    let right = folder.fold_expression(operator.right());
+    Box::new(OperatorExpression::new(left, operator.operator(), right))
+}
+
+pub fn fold_postfix<F: Fold + ?Sized>(
+    folder: &mut F,
+    postfix: &PostfixExpression,
+) -> Box<dyn Expression> {
+    let left = folder.fold_expression(postfix.left());
+    Box::new(PostfixExpression::new(left, postfix.operator()))
+}
+
+pub fn fold_conditional<F: Fold + ?Sized>(
+    folder: &mut F,
+    conditional: &ConditionalExpression,
+) -> Box<dyn Expression> {
+    let condition = folder.fold_expression(conditional.condition());
+    let then_arm = folder.fold_expression(conditional.then_arm());
+    let else_arm = folder.fold_expression(conditional.else_arm());
+    Box::new(ConditionalExpression::new(condition, then_arm, else_arm))
+}
+
+pub fn fold_assign<F: Fold + ?Sized>(
+    folder: &mut F,
+    assign: &AssignExpression,
+) -> Box<dyn Expression> {
+    let right = folder.fold_expression(assign.right());
+    Box::new(AssignExpression::new(assign.name().clone(), right))
+}
+
+pub fn fold_call<F: Fold + ?Sized>(folder: &mut F, call: &CallExpression) -> Box<dyn Expression> {
+    let function = folder.fold_expression(call.function());
+    let args = fold_all(folder, call.args());
+    Box::new(CallExpression::new(function, args))
+}
+
+pub fn fold_tuple<F: Fold + ?Sized>(
+    folder: &mut F,
+    tuple: &TupleExpression,
+) -> Box<dyn Expression> {
+    Box::new(TupleExpression::new(fold_all(folder, tuple.elements())))
+}
+
+pub fn fold_lambda<F: Fold + ?Sized>(
+    folder: &mut F,
+    lambda: &LambdaExpression,
+) -> Box<dyn Expression> {
+    let body = folder.fold_expression(lambda.body());
+    Box::new(LambdaExpression::new(lambda.params().to_vec(), body))
+}
+
+pub fn fold_section<F: Fold + ?Sized>(
+    folder: &mut F,
+    section: &SectionExpression,
+) -> Box<dyn Expression> {
+    let left = section.left().map(|left| folder.fold_expression(left));
+    let right = section.right().map(|right| folder.fold_expression(right));
+    Box::new(SectionExpression::new(left, section.operator(), right))
+}
+
+pub fn fold_optional_member<F: Fold + ?Sized>(
+    folder: &mut F,
+    member: &OptionalMemberExpression,
+) -> Box<dyn Expression> {
+    let object = folder.fold_expression(member.object());
+    Box::new(OptionalMemberExpression::new(object, member.name().clone()))
+}
+
+pub fn fold_optional_call<F: Fold + ?Sized>(
+    folder: &mut F,
+    call: &OptionalCallExpression,
+) -> Box<dyn Expression> {
+    let function = folder.fold_expression(call.function());
+    let args = fold_all(folder, call.args());
+    Box::new(OptionalCallExpression::new(function, args))
+}
+
+pub fn fold_pipeline<F: Fold + ?Sized>(
+    folder: &mut F,
+    pipeline: &PipelineExpression,
+) -> Box<dyn Expression> {
+    let value = folder.fold_expression(pipeline.value());
+    let function = folder.fold_expression(pipeline.function());
+    Box::new(PipelineExpression::new(value, function))
+}
+
+pub fn fold_match<F: Fold + ?Sized>(
+    folder: &mut F,
+    match_expression: &MatchExpression,
+) -> Box<dyn Expression> {
+    let value = folder.fold_expression(match_expression.value());
+    let arms = match_expression
+        .arms()
+        .iter()
+        .map(|arm| MatchArm::new(arm.pattern().clone(), folder.fold_expression(arm.body())))
+        .collect();
+    Box::new(MatchExpression::new(value, arms))
+}
+
+pub fn fold_let<F: Fold + ?Sized>(
+    folder: &mut F,
+    let_expression: &LetExpression,
+) -> Box<dyn Expression> {
+    let binding = folder.fold_expression(let_expression.binding());
+    let body = folder.fold_expression(let_expression.body());
+    Box::new(LetExpression::new(binding, body))
+}
+
+pub fn fold_block<F: Fold + ?Sized>(
+    folder: &mut F,
+    block: &BlockExpression,
+) -> Box<dyn Expression> {
+    Box::new(BlockExpression::new(fold_all(folder, block.statements())))
+}
@@ -1,15 +1,42 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashSet,
+    io::{self, BufReader, Read},
+    rc::Rc,
+};
 
-use bantam::core::{BantamParser, Lexer};
+use bantam::{
+    arena::{Arena, ExprKind},
+    builder::{AstBuilder, ExprBuilder},
+    core::{BantamParser, Lexer, Parser, Precedence, Span, StreamLexer, Token, TokenType},
+    expression::{
+        Expr, Expression, LambdaExpression, NameExpression, NumberExpression, OperatorExpression,
+        PrefixExpression,
+    },
+    grammar::Grammar,
+    json::to_json,
+    parselet::{
+        ApplicationParselet, ImplicitMultiplicationParselet, InfixEntry, PrefixEntry,
+        PrefixParselet,
+    },
+    visit::{walk_lambda, ExpressionVisitor, Fold},
+};
 
-struct BantamHarness {
-    parser: Rc<RefCell<BantamParser>>,
+struct BantamHarness<'src> {
+    parser: Rc<RefCell<BantamParser<'src>>>,
 }
 
-impl BantamHarness {
-    pub fn new(input: &str) -> Self {
-        let lexer = Lexer::new(input.to_owned());
-        let parser = BantamParser::new(Box::new(lexer));
+impl<'src> BantamHarness<'src> {
+    pub fn new(input: &'src str) -> Self {
+        Self::with_grammar(input, |_| {})
+    }
+
+    // Lets a test extend the default Bantam grammar before parsing
+    pub fn with_grammar(input: &'src str, configure: impl FnOnce(&mut BantamParser)) -> Self {
+        let lexer = Lexer::new(input);
+        let mut parser = BantamParser::new(Box::new(lexer));
+        configure(&mut parser);
 
         Self {
             parser: Rc::new(RefCell::new(parser)),
@@ -25,7 +52,13 @@ impl BantamHarness {
 }
 #[cfg(test)]
 mod tests {
-    use crate::BantamHarness;
+    use crate::{
+        io, to_json, walk_lambda, ApplicationParselet, Arena, AstBuilder, BantamHarness,
+        BantamParser, BufReader, Cow, Expr, ExprBuilder, ExprKind, Expression, ExpressionVisitor,
+        Fold, Grammar, HashSet, ImplicitMultiplicationParselet, InfixEntry, LambdaExpression,
+        Lexer, NameExpression, NumberExpression, OperatorExpression, Parser, Precedence,
+        PrefixEntry, PrefixExpression, PrefixParselet, Read, Span, StreamLexer, Token, TokenType,
+    };
 
     #[test]
     fn test_basic_calls() {
@@ -128,4 +161,1264 @@ mod tests {
             test_harness.run_test(expected);
         }
     }
+
+    #[test]
+    fn test_let_expression() {
+        let cases = vec![
+            ("let x = a + b in x * x", "(let (x = (a + b)) in (x * x))"),
+            (
+                "let x = 1 in let y = x in x + y",
+                "(let (x = 1) in (let (y = x) in (x + y)))",
+            ),
+            ("a + (let x = b in x)", "(a + (let (x = b) in x))"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_block_expression() {
+        let cases = vec![
+            (
+                "{ a = 1; b = a + 2; b * 2 }",
+                "{ (a = 1); (b = (a + 2)); (b * 2) }",
+            ),
+            ("{ a; }", "{ a }"),
+            ("{}", "{ }"),
+            ("f({ a = 2; a }, b)", "f({ (a = 2); a }, b)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_match_expression() {
+        let cases = vec![
+            (
+                "match x { 0 -> a, 1 -> b, _ -> c }",
+                "(match x { 0 -> a, 1 -> b, _ -> c })",
+            ),
+            (
+                "match a + b { (0, y) -> y, (x, _,) -> -x, }",
+                "(match (a + b) { (0, y) -> y, (x, _) -> (-x) })",
+            ),
+            (
+                "match x { ((y)) -> y ? a : b }",
+                "(match x { y -> (y ? a : b) })",
+            ),
+            ("match f(x) { () -> a }", "(match f(x) { () -> a })"),
+            ("match x { (a,) -> a }", "(match x { (a,) -> a })"),
+            ("a - match x { _ -> b }", "(a - (match x { _ -> b }))"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_pipeline_operator() {
+        let cases = vec![
+            ("x |> f |> g(1)", "((x |> f) |> g(1))"),
+            ("a + b |> f", "((a + b) |> f)"),
+            ("a ? x |> f : y |> g", "(a ? (x |> f) : (y |> g))"),
+            ("a = x |> f", "(a = (x |> f))"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_range_operators() {
+        let cases = vec![
+            ("a..b", "(a .. b)"),
+            ("0..=n", "(0 ..= n)"),
+            ("a + 1..b * 2", "((a + 1) .. (b * 2))"),
+            ("0..n |> f", "((0 .. n) |> f)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_null_coalescing() {
+        let cases = vec![
+            ("a ?? b", "(a ?? b)"),
+            ("a ?? b ?? c", "(a ?? (b ?? c))"),
+            ("a ?? b ? c : d", "((a ?? b) ? c : d)"),
+            ("a + b ?? c |> f", "((a + b) ?? (c |> f))"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_optional_chaining() {
+        let cases = vec![
+            ("a?.b", "a?.b"),
+            ("a?.b?.c", "a?.b?.c"),
+            ("a?(b)", "a?(b)"),
+            ("a?()?.b(c, d)", "a?()?.b(c, d)"),
+            ("-a?(b) + c?(d, e)", "((-a?(b)) + c?(d, e))"),
+            ("f(a?(b), c)", "f(a?(b), c)"),
+            ("a?(b) ? c : d", "(a?(b) ? c : d)"),
+            ("a ? b : c?(d)", "(a ? b : c?(d))"),
+            // `?` followed by `(` is a conditional if a `:` for it follows
+            ("a ?(b) : c", "(a ? b : c)"),
+            ("a?(b):c?(d)", "(a ? b : c?(d))"),
+            ("a ? (b) + 1 : c", "(a ? (b + 1) : c)"),
+            ("x + a ?(b) : c", "((x + a) ? b : c)"),
+            ("a ?(b) ? c : d : e", "(a ? (b ? c : d) : e)"),
+            ("a ?(b ? c : d) : e", "(a ? (b ? c : d) : e)"),
+            (
+                "match a?(b) { 0 -> c ?(d) : e }",
+                "(match a?(b) { 0 -> (c ? d : e) })",
+            ),
+            ("-a?.b ?? c", "((-a?.b) ?? c)"),
+            ("a?.b ? c : d", "(a?.b ? c : d)"),
+            ("a ? (b) : c", "(a ? b : c)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_operator_sections() {
+        let cases = vec![
+            ("(*)", "(*)"),
+            ("(+)", "(+)"),
+            ("(* 2)", "(* 2)"),
+            ("(/ a ^ b)", "(/ (a ^ b))"),
+            ("(^ a ^ b)", "(^ (a ^ b))"),
+            ("(2 *)", "(2 *)"),
+            ("(a ^ b +)", "((a ^ b) +)"),
+            ("(-a *)", "((-a) *)"),
+            ("(f(a, (b)) +)", "(f(a, b) +)"),
+            ("fold((+), zero, xs)", "fold((+), zero, xs)"),
+            ("map((^ 2), xs)", "map((^ 2), xs)"),
+            // Even when the operator is a prefix operator too
+            ("(+ 1)", "(+ 1)"),
+            ("(+ 1)(2)", "(+ 1)(2)"),
+            ("(+ -a)", "(+ (-a))"),
+            // Except for `-`, which keeps the prefix reading
+            ("(- 1)", "(-1)"),
+            ("a * (-b)", "(a * (-b))"),
+            ("(!a)!", "((!a)!)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_invalid_sections() {
+        // The operand of a section has to bind tighter than its operator
+        let cases = [
+            ("(a + b *)", "section (*)"),
+            ("(a + b +)", "section (+)"),
+            ("(a ^ b ^)", "section (^)"),
+            ("(* a + b)", "section (*)"),
+            ("(+ a + b)", "section (+)"),
+        ];
+
+        for (input, expected) in cases {
+            let error = std::panic::catch_unwind(|| Grammar::bantam().parse(input)).unwrap_err();
+            let message = error.downcast_ref::<String>().unwrap();
+            assert!(message.contains(expected), "{input}: {message}");
+        }
+    }
+
+    #[test]
+    fn test_implicit_multiplication() {
+        let cases = vec![
+            ("2x", "(2 * x)"),
+            ("3(a + b)", "(3 * (a + b))"),
+            ("2x y + 1", "(((2 * x) * y) + 1)"),
+            ("2x^2", "(2 * (x ^ 2))"),
+            ("a / 2x", "((a / 2) * x)"),
+            ("f(x)y", "(f(x) * y)"),
+            ("2 - x", "(2 - x)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::with_grammar(input, |bp: &mut BantamParser| {
+                bp.register_juxtaposition(Box::new(ImplicitMultiplicationParselet::new(
+                    Precedence::Product,
+                )))
+            });
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_juxtaposition_application() {
+        let cases = vec![
+            ("f a", "f(a)"),
+            ("f a b", "f(a)(b)"),
+            ("f a + g b", "(f(a) + g(b))"),
+            ("f (a + b) c", "f((a + b))(c)"),
+            ("f -a", "(f - a)"),
+            ("a b!", "(a(b)!)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::with_grammar(input, |bp: &mut BantamParser| {
+                bp.register_juxtaposition(Box::new(ApplicationParselet::new(Precedence::Call)))
+            });
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_declared_operators() {
+        let cases = vec![
+            ("infixl 6 <+> a <+> b <+> c * d", "((a <+> b) <+> (c * d))"),
+            ("infixr 8 ^^; a ^^ b ^^ c", "(a ^^ (b ^^ c))"),
+            ("prefix 9 # #a + b", "((#a) + b)"),
+            ("postfix 10 ?! a?! * b", "((a?!) * b)"),
+            (
+                "infixl 7 <*>\ninfixl 6 <+>\na <+> b <*> c",
+                "(a <+> (b <*> c))",
+            ),
+            // Redeclaring a built-in operator changes its precedence
+            ("infixl 8 + a * b + c", "(a * (b + c))"),
+            // Before the declaration `<+>` is just `+` with ignored chars around it
+            ("a <+> b", "(a + b)"),
+            // Anywhere a declaration can't start the keywords are ordinary names
+            ("f(prefix, infixl)", "f(prefix, infixl)"),
+            ("postfix", "postfix"),
+            ("prefix + 9", "(prefix + 9)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_invalid_declarations() {
+        let cases = [
+            ("infixl 6 ( a ( b", "found LEFT_PAREN"),
+            ("infixl 6 , f(a, b)", "found COMMA"),
+            ("infixr 6 -> a -> b", "found ARROW"),
+            ("prefix 9 { a", "found LEFT_BRACE"),
+            ("infixl 12 <+> a <+> b", "Invalid precedence 12"),
+            (
+                "infixr 0 <> a <> b <> c",
+                "Invalid precedence 0 in the declaration of an operator, expected 1 to 11",
+            ),
+            (
+                "infixl 99999999999999999999999 <+> a <+> b",
+                "Invalid precedence 99999999999999999999999",
+            ),
+            (
+                "infixl 6 ~~~~~~~~~~~~~~~~~ a",
+                "Could not declare ~~~~~~~~~~~~~~~~~",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let error = std::panic::catch_unwind(|| Grammar::bantam().parse(input)).unwrap_err();
+            let message = error.downcast_ref::<String>().unwrap();
+            assert!(message.contains(expected), "{input}: {message}");
+        }
+    }
+
+    #[test]
+    fn test_binding_power_pairs() {
+        // A prefix `-` that binds looser than `^` on its right, but still
+        // tighter than `*`
+        let cases = vec![
+            ("-a ^ b", "(-(a ^ b))"),
+            ("-a * b", "((-a) * b)"),
+            ("-a ^ b * c", "((-(a ^ b)) * c)"),
+            ("a ^ -b ^ c", "(a ^ (-(b ^ c)))"),
+            ("~a ^ b", "((~a) ^ b)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::with_grammar(input, |bp: &mut BantamParser| {
+                let exponent = Precedence::Exponent.binding_power();
+                bp.prefix_binding_power(TokenType::Minus, exponent - 1)
+            });
+            test_harness.run_test(expected);
+        }
+
+        // `..` binds tighter than `+` on its left but looser on its right
+        let cases = vec![
+            ("a + b .. c + d", "(a + (b .. (c + d)))"),
+            ("a * b .. c", "((a * b) .. c)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::with_grammar(input, |bp: &mut BantamParser| {
+                let sum = Precedence::Sum.binding_power();
+                bp.infix_binding_power(TokenType::DotDot, sum + 2, sum - 1)
+            });
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_mixed_associativity() {
+        // A right-associative operator takes in everything of its own level on
+        // its right, whatever the associativity of the other operators
+        let cases = vec![
+            ("a * b + c", "(a * (b + c))"),
+            ("a + b * c", "((a + b) * c)"),
+            ("a * b * c + d", "(a * (b * (c + d)))"),
+            ("a - b * c - d", "((a - b) * (c - d))"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::with_grammar(input, |bp: &mut BantamParser| {
+                bp.infix_right(TokenType::Asterisk, Precedence::Sum)
+            });
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_lambdas_tuples_and_groups() {
+        let cases = vec![
+            ("(a, b) -> a + b", "((a, b) -> (a + b))"),
+            ("(x) -> x", "((x) -> x)"),
+            ("() -> f()", "(() -> f())"),
+            ("map((x) -> x * 2, xs)", "map(((x) -> (x * 2)), xs)"),
+            ("(a, b) -> (c) -> a", "((a, b) -> ((c) -> a))"),
+            ("(a, b)", "(a, b)"),
+            ("(a, b + c, d,)", "(a, (b + c), d)"),
+            ("(a,)", "(a,)"),
+            ("()", "()"),
+            ("(a, b)(c)", "(a, b)(c)"),
+            ("(a) + b", "(a + b)"),
+            ("(a, b) + c", "((a, b) + c)"),
+            (
+                "match (a, b) { (x, _) -> x }",
+                "(match (a, b) { (x, _) -> x })",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::new(input);
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    fn test_mark_and_rewind() {
+        let mut parser = Parser::new(Box::new(Lexer::new("a b c d")));
+        assert_eq!(parser.peek(2).get_text(), "c");
+
+        let mark = parser.mark();
+        assert_eq!(parser.consume().get_text(), "a");
+        assert_eq!(parser.consume().get_text(), "b");
+        parser.rewind(mark);
+        assert_eq!(parser.peek(0).get_text(), "a");
+
+        let none: Option<()> = parser.try_parse(|p| {
+            p.consume();
+            p.consume();
+            None
+        });
+        assert!(none.is_none());
+        assert_eq!(parser.consume().get_text(), "a");
+
+        let name = parser.try_parse(|p| Some(p.consume().text));
+        assert_eq!(name.as_deref(), Some("b"));
+        assert_eq!(parser.consume().get_text(), "c");
+    }
+
+    #[test]
+    fn test_match_head_restrictions() {
+        let cases = vec![
+            ("match f x { y -> y }", "(match f(x) { y -> y })"),
+            ("match (a = b) { _ -> c }", "(match (a = b) { _ -> c })"),
+            ("match f({ a }) { _ -> c }", "(match f({ a }) { _ -> c })"),
+            ("a ? b = c : d", "(a ? (b = c) : d)"),
+            ("a ? (b = c) : d", "(a ? (b = c) : d)"),
+            ("a = b ? c : d", "(a = (b ? c : d))"),
+            ("f(a = b) ? c : d", "(f((a = b)) ? c : d)"),
+            ("((a = b)) + c", "((a = b) + c)"),
+            ("(a = b)?(c)", "(a = b)?(c)"),
+        ];
+
+        for (input, expected) in cases {
+            let test_harness = BantamHarness::with_grammar(input, |bp: &mut BantamParser| {
+                bp.register_juxtaposition(Box::new(ApplicationParselet::new(Precedence::Call)))
+            });
+            test_harness.run_test(expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "assignment is not allowed in the head of a match")]
+    fn test_no_assignment_in_match_head() {
+        BantamHarness::new("match a = b { _ -> c }").run_test("");
+    }
+
+    #[test]
+    fn test_no_assignment_in_condition() {
+        let cases = [
+            "(a = b) ? c : d",
+            "((a = b)) ? c : d",
+            "x + (a = b) ? c : d",
+            "(a = b) ?(c) : d",
+        ];
+
+        for input in cases {
+            let error = std::panic::catch_unwind(|| Grammar::bantam().parse(input)).unwrap_err();
+            let message = error.downcast_ref::<String>().unwrap();
+            assert_eq!(
+                message, "assignment is not allowed in the condition of a conditional expression",
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "a block is not allowed in the head of a match")]
+    fn test_no_block_in_match_head() {
+        BantamHarness::new("match { a } { _ -> b }").run_test("");
+    }
+
+    #[test]
+    fn test_shared_grammar() {
+        let mut grammar = Grammar::bantam();
+        grammar.infix_right(TokenType::Asterisk, Precedence::Product);
+
+        let cases = vec![
+            ("a * b * c", "(a * (b * c))"),
+            ("infixl 6 <+> a <+> b <+> c", "((a <+> b) <+> c)"),
+            ("-a!", "(-(a!))"),
+        ];
+
+        for (input, expected) in cases {
+            let mut actual = String::new();
+            grammar.parse(input).print(&mut actual);
+            assert_eq!(actual, expected);
+        }
+
+        // Neither the change above nor the declared operator leak into
+        // other users of the Bantam grammar
+        let mut actual = String::new();
+        Grammar::bantam().parse("a * b * c").print(&mut actual);
+        assert_eq!(actual, "((a * b) * c)");
+
+        let tokens = Lexer::new("a + b");
+        let mut parser = grammar.parser(Box::new(tokens));
+        assert!(parser
+            .grammar()
+            .get_infix(TokenType::operator("<+>").unwrap())
+            .is_none());
+        let mut actual = String::new();
+        parser.parse_expression().print(&mut actual);
+        assert_eq!(actual, "(a + b)");
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let inputs: Vec<String> = (0..100)
+            .map(|i| format!("a{} * b + -c{}!", i % 7, i))
+            .collect();
+        let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+
+        let grammar = Grammar::bantam();
+        let results = grammar.parse_batch(&inputs);
+        assert_eq!(results.len(), inputs.len());
+
+        for (input, result) in inputs.iter().zip(results) {
+            let mut expected = String::new();
+            grammar.parse(input).print(&mut expected);
+            let mut actual = String::new();
+            result.print(&mut actual);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_grammar_is_thread_safe() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Grammar>();
+
+        let grammar = Grammar::bantam();
+        let handle = std::thread::spawn(move || {
+            let mut actual = String::new();
+            grammar.parse("a ?? b |> f").print(&mut actual);
+            actual
+        });
+        assert_eq!(handle.join().unwrap(), "(a ?? (b |> f))");
+    }
+
+    #[test]
+    fn test_token_type_index() {
+        let values = TokenType::values();
+        assert_eq!(values.len(), TokenType::COUNT);
+        for (i, tt) in values.iter().enumerate() {
+            assert_eq!(tt.index(), Some(i));
+        }
+
+        assert_eq!(TokenType::operator("<+>").unwrap().index(), None);
+        assert_eq!(TokenType::operator("(+)"), None);
+        assert_eq!(TokenType::operator(&"~".repeat(17)), None);
+        assert_eq!(
+            TokenType::operator(&"~".repeat(16)).unwrap().punctuator(),
+            Some("~".repeat(16).as_str())
+        );
+    }
+
+    #[test]
+    fn test_many_declared_operators() {
+        // There is no limit on how many operators can be declared over time,
+        // each parse only keeps its own
+        let chars = ['<', '>', '&', '|', '%', '$', '#', '@', '*', '+', '~', '!'];
+        let mut declared = 0;
+        for a in chars {
+            for b in chars {
+                for c in chars {
+                    let symbol = format!("{a}{b}{c}");
+                    let input = format!("infixl 6 {symbol} x {symbol} y");
+                    let mut actual = String::new();
+                    Grammar::bantam().parse(&input).print(&mut actual);
+                    assert_eq!(actual, format!("(x {symbol} y)"));
+                    declared += 1;
+                }
+            }
+        }
+        assert!(declared > 1024);
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let input = "λ + foo(12) |> let";
+        let tokens: Vec<Token> = Lexer::new(input).take(9).collect();
+
+        let texts: Vec<&str> = tokens
+            .iter()
+            .map(|t| &input[t.get_span().start..t.get_span().end])
+            .collect();
+        assert_eq!(texts, ["λ", "+", "foo", "(", "12", ")", "|>", "let", ""]);
+        for (token, text) in tokens.iter().zip(texts) {
+            assert_eq!(token.get_text(), text);
+        }
+
+        // No token allocates its text. Names and numbers point into the source
+        assert!(tokens.iter().all(|t| matches!(t.text, Cow::Borrowed(_))));
+        assert_eq!(tokens[2].get_text().as_ptr(), input[5..].as_ptr());
+
+        let owned: Vec<Token<'static>> = Lexer::new(input).into_owned().take(3).collect();
+        assert!(matches!(owned[2].text, Cow::Owned(_)));
+        assert_eq!(owned[2].get_text(), "foo");
+        assert_eq!(owned[2].get_span(), tokens[2].get_span());
+    }
+
+    #[test]
+    fn test_stream_lexer() {
+        let input = "λ ?? foo(12); infixl 6 <+> a <+> b; c?.d";
+
+        // Reading a byte at a time splits every token and every char
+        let reader = BufReader::with_capacity(1, input.as_bytes());
+        let streamed: Vec<Token> = StreamLexer::new(reader).take(20).collect();
+        let lexed: Vec<Token> = Lexer::new(input).take(20).collect();
+        for (streamed, lexed) in streamed.iter().zip(&lexed) {
+            assert_eq!(streamed.get_type(), lexed.get_type());
+            assert_eq!(streamed.get_text(), lexed.get_text());
+            assert_eq!(streamed.get_span(), lexed.get_span());
+        }
+
+        let reader = BufReader::with_capacity(3, input.as_bytes());
+        let mut parser = BantamParser::new(Box::new(StreamLexer::new(reader)));
+        let mut statements = Vec::new();
+        while let Some(statement) = parser.parse_statement() {
+            let mut actual = String::new();
+            statement.print(&mut actual);
+            statements.push(actual);
+        }
+        assert_eq!(statements, ["(λ ?? foo(12))", "(a <+> b)", "c?.d"]);
+    }
+
+    #[test]
+    fn test_stream_lexer_errors() {
+        // Hands out its input a few bytes at a time, with an interruption in
+        // between, and then fails for good
+        struct FlakyReader {
+            input: &'static [u8],
+            interrupted: bool,
+        }
+        impl Read for FlakyReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.interrupted = !self.interrupted;
+                if self.interrupted {
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "again"));
+                }
+                if self.input.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::ConnectionReset, "gone"));
+                }
+                let len = self.input.len().min(buf.len()).min(3);
+                buf[..len].copy_from_slice(&self.input[..len]);
+                self.input = &self.input[len..];
+                Ok(len)
+            }
+        }
+
+        let texts = |lexer: &mut dyn Iterator<Item = Token<'static>>| {
+            lexer
+                .map(|token| token.get_text().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut lexer = StreamLexer::from_reader(FlakyReader {
+            input: b"foo(12) + bar",
+            interrupted: false,
+        });
+        assert_eq!(texts(&mut lexer), ["foo", "(", "12", ")", "+", "bar", ""]);
+        assert_eq!(
+            lexer.error().unwrap().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+
+        // Everything before the invalid bytes still comes through
+        let mut lexer = StreamLexer::from_reader(&b"a + b\xff + c"[..]);
+        assert_eq!(texts(&mut lexer), ["a", "+", "b", ""]);
+        let error = lexer.error().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Invalid UTF-8 at byte 5");
+
+        let mut lexer = StreamLexer::from_reader("a λ".as_bytes().split_last().unwrap().1);
+        assert_eq!(texts(&mut lexer), ["a", ""]);
+        assert_eq!(lexer.error().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut lexer = StreamLexer::from_reader(&b"a + b"[..]);
+        assert_eq!(texts(&mut lexer), ["a", "+", "b", ""]);
+        assert!(lexer.error().is_none());
+    }
+
+    #[test]
+    fn test_external_tokens() {
+        // A parselet for numbers that another tokenizer has already parsed
+        struct HexParselet;
+        impl PrefixParselet for HexParselet {
+            fn parse(&self, _parser: &mut Parser, token: Token) -> Box<dyn Expression> {
+                let value = token.get_payload::<u64>().unwrap();
+                Box::new(NumberExpression::new(value.to_string()))
+            }
+        }
+
+        let mut grammar = Grammar::bantam();
+        grammar.register_prefix(TokenType::Number, Box::new(HexParselet));
+
+        // No EOF at the end, running out of tokens is enough
+        let tokens = vec![
+            Token::with_span(TokenType::Name, "a", Span::new(0, 1)),
+            Token::with_span(TokenType::Asterisk, "*", Span::new(2, 3)),
+            Token::with_span(TokenType::Number, "0x1f", Span::new(4, 8)).with_payload(31u64),
+        ];
+        let mut parser = grammar.parser(tokens);
+        let mut actual = String::new();
+        parser.parse_expression().print(&mut actual);
+        assert_eq!(actual, "(a * 31)");
+
+        let eof = parser.consume();
+        assert_eq!(*eof.get_type(), TokenType::EOF);
+        assert_eq!(eof.get_span(), Span::new(8, 8));
+
+        // The lexer ends with a single EOF too
+        let types: Vec<TokenType> = Lexer::new("a").map(|t| *t.get_type()).collect();
+        assert_eq!(types, [TokenType::Name, TokenType::EOF]);
+    }
+
+    #[test]
+    fn test_iterative_parsing() {
+        let cases = vec![
+            "a + b * c - d / e ^ f ^ g",
+            "-a! + ~!b * -(c + d)!",
+            "a ? b : c ? d : e",
+            "x + a?(b) ? c ?(d) : e : f",
+            "f(a + b, -c)(d) |> g ?? h .. i",
+            "let x = a + b in { y = -x; y * x! }",
+            "match a + b { 0 -> -c, (d, _) -> d ^ e }",
+            "infixr 8 ** prefix 9 # a ** #b ** c",
+        ];
+
+        for input in cases {
+            let expected = BantamParser::new(Lexer::new(input)).parse_expression();
+            let actual = BantamParser::new(Lexer::new(input)).parse_expression_iterative();
+            assert_eq!(actual, expected);
+        }
+
+        // Far deeper than parse_expression() can go
+        let depth = 10_000;
+        let input = format!("{}a ^ {}", "-".repeat(depth), vec!["b"; depth].join(" ^ "));
+        let expression = Grammar::bantam()
+            .parser(Lexer::new(&input))
+            .parse_expression_iterative();
+        let mut actual = String::new();
+        expression.print(&mut actual);
+        let prefixes = format!("({}a{}", "(-".repeat(depth), ")".repeat(depth));
+        assert!(actual.starts_with(&format!("{} ^ (b ^ (b ^", prefixes)));
+        assert!(actual.ends_with(&format!("(b ^ b){}", ")".repeat(depth - 1))));
+
+        // Deep enough that dropping the tree recursively overflows the stack
+        let depth = 300_000;
+        let input = format!("{}a", "-".repeat(depth));
+        let expression = Grammar::bantam()
+            .parser(Lexer::new(&input))
+            .parse_expression_iterative();
+        let mut node = expression.as_ref();
+        let mut prefixes = 0;
+        while let Some(prefix) = node.as_any().downcast_ref::<PrefixExpression>() {
+            node = prefix.right();
+            prefixes += 1;
+        }
+        assert_eq!(prefixes, depth);
+        drop(expression);
+    }
+
+    #[test]
+    fn test_parselet_entries() {
+        let mut grammar = Grammar::bantam();
+        assert!(matches!(
+            grammar.get_prefix(TokenType::Name),
+            Some(PrefixEntry::Name(_))
+        ));
+        assert!(matches!(
+            grammar.get_infix(TokenType::Plus),
+            Some(InfixEntry::Binary(_))
+        ));
+        assert!(matches!(
+            grammar.get_infix(TokenType::Bang),
+            Some(InfixEntry::Postfix(_))
+        ));
+
+        let parselet = ImplicitMultiplicationParselet::new(Precedence::Product);
+        grammar.register_infix(TokenType::Underscore, Box::new(parselet));
+        assert!(matches!(
+            grammar.get_infix(TokenType::Underscore),
+            Some(InfixEntry::Custom(_))
+        ));
+
+        let mut actual = String::new();
+        grammar.parse("a + b _ c").print(&mut actual);
+        assert_eq!(actual, "(a + (b * c))");
+    }
+
+    #[test]
+    fn test_evaluating_builder() {
+        // Works out the value while parsing, without building a tree
+        #[derive(Default)]
+        struct Calculator;
+
+        impl ExprBuilder for Calculator {
+            type Output = f64;
+
+            fn name(&mut self, name: &str) -> f64 {
+                panic!("unknown variable {name}")
+            }
+
+            fn number(&mut self, value: &str) -> f64 {
+                value.parse().unwrap()
+            }
+
+            fn prefix(&mut self, operator: TokenType, right: f64) -> f64 {
+                match operator {
+                    TokenType::Minus => -right,
+                    _ => right,
+                }
+            }
+
+            fn binary(&mut self, left: f64, operator: TokenType, right: f64) -> f64 {
+                match operator {
+                    TokenType::Plus => left + right,
+                    TokenType::Minus => left - right,
+                    TokenType::Asterisk => left * right,
+                    TokenType::Slash => left / right,
+                    TokenType::Caret => left.powf(right),
+                    _ => panic!("unsupported operator {operator}"),
+                }
+            }
+
+            fn postfix(&mut self, left: f64, _operator: TokenType) -> f64 {
+                (1..=left as u64).product::<u64>() as f64
+            }
+
+            fn is_number(&self, _expr: &f64) -> bool {
+                true
+            }
+        }
+
+        let mut grammar = Grammar::<Calculator>::build_bantam();
+        assert_eq!(grammar.parse("1 + 2 * 3 ^ 2 - -4"), 23.0);
+        assert_eq!(grammar.parse("(2 + 3)! / 4"), 30.0);
+        let mut parser = grammar.parser(Lexer::new("2 ^ 3 ^ 2"));
+        assert_eq!(parser.parse_expression_iterative(), 512.0);
+
+        let parselet = ImplicitMultiplicationParselet::new(Precedence::Product);
+        grammar.register_juxtaposition(Box::new(parselet));
+        assert_eq!(grammar.parse("3(1 + 1)"), 6.0);
+    }
+
+    #[test]
+    fn test_builder_state() {
+        // Emits code for a stack machine and only returns ()
+        #[derive(Default)]
+        struct Emitter {
+            code: Vec<String>,
+        }
+
+        impl ExprBuilder for Emitter {
+            type Output = ();
+
+            fn name(&mut self, name: &str) {
+                self.code.push(format!("load {name}"));
+            }
+
+            fn number(&mut self, value: &str) {
+                self.code.push(format!("push {value}"));
+            }
+
+            fn prefix(&mut self, operator: TokenType, _right: ()) {
+                self.code
+                    .push(format!("prefix {}", operator.punctuator().unwrap()));
+            }
+
+            fn binary(&mut self, _left: (), operator: TokenType, _right: ()) {
+                self.code
+                    .push(format!("binary {}", operator.punctuator().unwrap()));
+            }
+
+            fn postfix(&mut self, _left: (), operator: TokenType) {
+                self.code
+                    .push(format!("postfix {}", operator.punctuator().unwrap()));
+            }
+
+            fn call(&mut self, _function: (), args: Vec<()>) {
+                self.code.push(format!("call {}", args.len()));
+            }
+        }
+
+        let grammar = Grammar::<Emitter>::build_bantam();
+        let mut parser = grammar.parser(Lexer::new("f(a, -b) + 2!"));
+        parser.parse_expression();
+        assert_eq!(
+            parser.into_builder().code,
+            [
+                "load f",
+                "load a",
+                "load b",
+                "prefix -",
+                "call 2",
+                "push 2",
+                "postfix !",
+                "binary +",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_enum_ast() {
+        let inputs = [
+            "a = -b! ? c(d, e) : (f, g)",
+            "x |> (* 2) |> (1 +) |> (-) |> h?.i?(j)",
+            "let x = (a, b) -> a ^ b in { y = x(1, 2); y }",
+            "match a { 0 -> b, (c, _) -> c, _ -> () }",
+        ];
+
+        let grammar = Grammar::<AstBuilder>::build_bantam();
+        for input in inputs {
+            let mut expected = String::new();
+            let tree = Grammar::bantam().parse(input);
+            tree.print(&mut expected);
+
+            let mut built = String::new();
+            grammar.parse(input).print(&mut built);
+            assert_eq!(built, expected);
+
+            let mut converted = String::new();
+            Expr::from(tree).print(&mut converted);
+            assert_eq!(converted, expected);
+        }
+
+        let Expr::Assign { name, right, .. } = grammar.parse("a = b + 1") else {
+            panic!("expected an assignment");
+        };
+        assert_eq!(name, "a");
+        assert!(matches!(
+            *right,
+            Expr::Binary {
+                operator: TokenType::Plus,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_expr_spans() {
+        let grammar = Grammar::<AstBuilder>::build_bantam();
+        assert_eq!(
+            to_json(&grammar.parse("-a")),
+            concat!(
+                r#"{"kind":"prefix","operator":"-","#,
+                r#""right":{"kind":"name","name":"a","span":{"start":1,"end":2}},"#,
+                r#""span":{"start":0,"end":2}}"#,
+            )
+        );
+
+        let expr = grammar.parse("a + (b * c)");
+        assert_eq!(expr.span(), Some(Span::new(0, 11)));
+        let Expr::Binary { left, right, .. } = &expr else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(left.span(), Some(Span::new(0, 1)));
+        // The parentheses go with the expression inside them
+        assert_eq!(right.span(), Some(Span::new(4, 11)));
+
+        // Spans don't make expressions different
+        assert_eq!(grammar.parse("a+(b*c)"), expr);
+
+        // The iterative parser finds the same spans
+        for input in [
+            "-a! + f(x, y)?.z * 2 ^ -3",
+            "a = b ? c : d",
+            "x |> (* 2) |> { y; match a { 0 -> b } }",
+        ] {
+            let mut parser = grammar.parser(Lexer::new(input));
+            assert_eq!(
+                to_json(&parser.parse_expression_iterative()),
+                to_json(&grammar.parse(input))
+            );
+        }
+
+        // The Expression trees don't keep track of them
+        let tree = Grammar::bantam().parse("a + (b * c)");
+        assert_eq!(Expr::from(tree.as_ref()).span(), None);
+        assert!(!to_json(tree.as_ref()).contains("span"));
+    }
+
+    #[test]
+    fn test_visitor() {
+        // Collects the names that are used but not bound by a lambda
+        #[derive(Default)]
+        struct FreeNames {
+            bound: Vec<String>,
+            free: Vec<String>,
+        }
+
+        impl ExpressionVisitor for FreeNames {
+            fn visit_name(&mut self, name: &NameExpression) {
+                if !self.bound.contains(name.name()) {
+                    self.free.push(name.name().clone());
+                }
+            }
+
+            fn visit_lambda(&mut self, lambda: &LambdaExpression) {
+                let outer = self.bound.len();
+                self.bound.extend_from_slice(lambda.params());
+                walk_lambda(self, lambda);
+                self.bound.truncate(outer);
+            }
+        }
+
+        let expression = Grammar::bantam().parse("f((x, y) -> x + z, -y) |> g(x)?.h");
+        let mut free_names = FreeNames::default();
+        free_names.visit_expression(expression.as_ref());
+        assert_eq!(free_names.free, ["f", "z", "y", "g", "x"]);
+
+        // A visitor that computes something overrides every node it can meet
+        struct Evaluate;
+
+        impl ExpressionVisitor<f64> for Evaluate {
+            fn visit_number(&mut self, number: &NumberExpression) -> f64 {
+                number.value().parse().unwrap()
+            }
+
+            fn visit_prefix(&mut self, prefix: &PrefixExpression) -> f64 {
+                -self.visit_expression(prefix.right())
+            }
+
+            fn visit_operator(&mut self, operator: &OperatorExpression) -> f64 {
+                let left = self.visit_expression(operator.left());
+                let right = self.visit_expression(operator.right());
+                match operator.operator() {
+                    TokenType::Plus => left + right,
+                    TokenType::Asterisk => left * right,
+                    operator => panic!("unsupported operator {operator}"),
+                }
+            }
+        }
+
+        let expression = Grammar::bantam().parse("2 * -(3 + 4)");
+        assert_eq!(Evaluate.visit_expression(expression.as_ref()), -14.0);
+    }
+
+    #[test]
+    fn test_fold() {
+        // Adds up sums of numbers and leaves everything else as it is
+        struct FoldConstants;
+
+        impl Fold for FoldConstants {
+            fn fold_operator(&mut self, operator: &OperatorExpression) -> Box<dyn Expression> {
+                let left = self.fold_expression(operator.left());
+                let right = self.fold_expression(operator.right());
+                let number = |e: &dyn Expression| {
+                    e.as_any()
+                        .downcast_ref::<NumberExpression>()
+                        .map(|n| n.value().parse::<i64>().unwrap())
+                };
+
+                match (
+                    number(left.as_ref()),
+                    operator.operator(),
+                    number(right.as_ref()),
+                ) {
+                    (Some(l), TokenType::Plus, Some(r)) => {
+                        Box::new(NumberExpression::new((l + r).to_string()))
+                    }
+                    _ => Box::new(OperatorExpression::new(left, operator.operator(), right)),
+                }
+            }
+        }
+
+        let cases = vec![
+            ("1 + 2 + 3", "6"),
+            (
+                "f(1 + 2, a + 1 + 2) ? 3 + 4 : -(5 + 6)!",
+                "(f(3, ((a + 1) + 2)) ? 7 : (-(11!)))",
+            ),
+            (
+                "let x = 1 + 1 in match x { 2 -> (* (2 + 2)) }",
+                "(let (x = 2) in (match x { 2 -> (* 4) }))",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let expression = Grammar::bantam().parse(input);
+            let mut actual = String::new();
+            FoldConstants
+                .fold_expression(expression.as_ref())
+                .print(&mut actual);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_expression_traits() {
+        let grammar = Grammar::bantam();
+        let tree = grammar.parse("a + b * -c!");
+        assert_eq!(tree, grammar.parse("a+b*(-c!)"));
+        assert_ne!(tree, grammar.parse("(a + b) * -c!"));
+        assert_eq!(tree.clone(), tree);
+
+        assert_eq!(
+            format!("{:?}", grammar.parse("-a")),
+            r#"PrefixExpression { operator: Minus, right: NameExpression { name: "a" } }"#
+        );
+
+        // Nodes of different types don't compare equal even if they print the same
+        let expr = Expr::from(tree.clone());
+        assert_ne!(&expr as &dyn Expression, tree.as_ref());
+        assert_eq!(
+            expr,
+            Grammar::<AstBuilder>::build_bantam().parse("a + b * -c!")
+        );
+
+        let mut seen = HashSet::new();
+        for input in ["f(x, y)", "f(x,y)", "f(y, x)", "(f)(x, y)"] {
+            seen.insert(grammar.parse(input));
+        }
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[test]
+    fn test_arena() {
+        let input = "let x = f(a, -b) in x ? (* 2) : { y = x; y! }";
+        let mut parser = Grammar::<Arena>::build_bantam().parser(Lexer::new(input));
+        let root = parser.parse_expression();
+        let arena = parser.into_builder();
+
+        let mut expected = String::new();
+        Grammar::bantam().parse(input).print(&mut expected);
+        let mut actual = String::new();
+        arena.to_expr(root).print(&mut actual);
+        assert_eq!(actual, expected);
+
+        // The name and the assignment became the let, so nothing is left over
+        let orphans: Vec<_> = arena
+            .ids()
+            .filter(|&id| arena.parent(id).is_none())
+            .collect();
+        assert_eq!(orphans, [root]);
+
+        let cursor = arena.cursor(root);
+        assert_eq!(cursor.kind(), &ExprKind::Let("x".to_string()));
+        let call = cursor.first_child().unwrap();
+        assert_eq!(call.kind(), &ExprKind::Call);
+        let a = call.child(1).unwrap();
+        assert_eq!(a.kind(), &ExprKind::Name("a".to_string()));
+        assert_eq!(
+            a.prev_sibling().unwrap().kind(),
+            &ExprKind::Name("f".to_string())
+        );
+        assert_eq!(
+            a.next_sibling().unwrap().kind(),
+            &ExprKind::Prefix(TokenType::Minus)
+        );
+        assert!(a.next_sibling().unwrap().next_sibling().is_none());
+        assert_eq!(a.parent().unwrap().parent().unwrap().id(), root);
+
+        // Side tables are indexed by id, here the depth of every node
+        let mut depths = vec![0; arena.len()];
+        for id in arena.ids() {
+            let mut cursor = arena.cursor(id);
+            while let Some(parent) = cursor.parent() {
+                depths[id.index()] += 1;
+                cursor = parent;
+            }
+        }
+        let block = cursor.child(1).unwrap().last_child().unwrap();
+        assert_eq!(block.kind(), &ExprKind::Block);
+        let factorial = block.last_child().unwrap();
+        assert_eq!(depths[factorial.id().index()], 3);
+        assert_eq!(depths.iter().max(), Some(&4));
+    }
+
+    #[test]
+    fn test_json() {
+        let expression = Grammar::bantam().parse("(* 2) |> -f!");
+        assert_eq!(
+            to_json(expression.as_ref()),
+            concat!(
+                r#"{"kind":"pipeline","#,
+                r#""value":{"kind":"section","left":null,"operator":"*","#,
+                r#""right":{"kind":"number","value":"2"}},"#,
+                r#""function":{"kind":"prefix","operator":"-","#,
+                r#""right":{"kind":"postfix","left":{"kind":"name","name":"f"},"operator":"!"}}}"#,
+            )
+        );
+
+        let expression = Grammar::bantam().parse("match a { (0, x) -> x, _ -> () }");
+        assert_eq!(
+            to_json(expression.as_ref()),
+            concat!(
+                r#"{"kind":"match","value":{"kind":"name","name":"a"},"arms":["#,
+                r#"{"pattern":{"kind":"tuple","elements":[{"kind":"number","value":"0"},"#,
+                r#"{"kind":"name","name":"x"}]},"body":{"kind":"name","name":"x"}},"#,
+                r#"{"pattern":{"kind":"wildcard"},"body":{"kind":"tuple","elements":[]}}]}"#,
+            )
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_serde() {
+        let inputs = [
+            "a = -b! ? c(d, e) : (f, g)",
+            "x |> (* 2) |> (1 +) |> (-) |> h?.i?(j)",
+            "let x = (a, b) -> a ^ b in { y = x(1, 2); y }",
+            "match a { 0 -> b, (c, _) -> c, _ -> () }",
+            "infixl 6 <+> a <+> b",
+        ];
+
+        for input in inputs {
+            let tree = Grammar::bantam().parse(input);
+            let json = serde_json::to_string(&tree).unwrap();
+            assert_eq!(json, to_json(tree.as_ref()));
+
+            let read: Box<dyn Expression> = serde_json::from_str(&json).unwrap();
+            assert_eq!(read, tree);
+            let read: Expr = serde_json::from_str(&json).unwrap();
+            assert_eq!(read, Expr::from(tree));
+        }
+
+        // Spans are written in the same place and read back into an Expr
+        let grammar = Grammar::<AstBuilder>::build_bantam();
+        for input in inputs {
+            let expr = grammar.parse(input);
+            let json = serde_json::to_string(&expr).unwrap();
+            assert_eq!(json, to_json(&expr));
+
+            let read: Expr = serde_json::from_str(&json).unwrap();
+            assert_eq!(to_json(&read), json);
+        }
+
+        // Spans are allowed on any node, a tree skips them
+        let json = r#"{"kind":"binary","span":{"start":0,"end":5},
+            "left":{"kind":"name","name":"a","span":{"start":0,"end":1}},
+            "operator":"+","right":{"kind":"number","value":"1"}}"#;
+        let read: Expr = serde_json::from_str(json).unwrap();
+        assert_eq!(read.span(), Some(Span::new(0, 5)));
+        let Expr::Binary { left, right, .. } = &read else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(left.span(), Some(Span::new(0, 1)));
+        assert_eq!(right.span(), None);
+        let read: Box<dyn Expression> = serde_json::from_str(json).unwrap();
+        assert_eq!(to_json(read.as_ref()), to_json(&Expr::from(read.as_ref())));
+
+        // Operators don't have to be declared anywhere to be read
+        let json = r#"{"kind":"binary","left":{"kind":"name","name":"a"},
+            "operator":"<*>","right":{"kind":"number","value":"1"}}"#;
+        let read: Box<dyn Expression> = serde_json::from_str(json).unwrap();
+        let mut printed = String::new();
+        read.print(&mut printed);
+        assert_eq!(printed, "(a <*> 1)");
+
+        // and reading any number of them doesn't use anything up that
+        // declarations need
+        for i in 0..2000 {
+            let symbol: String = format!("{i:o}")
+                .bytes()
+                .map(|digit| b"+*/~%&|^"[(digit - b'0') as usize] as char)
+                .collect();
+            let json = format!(
+                r#"{{"kind":"prefix","operator":"<{symbol}>","right":{{"kind":"name","name":"a"}}}}"#
+            );
+            serde_json::from_str::<Expr>(&json).unwrap();
+        }
+        let mut printed = String::new();
+        Grammar::bantam()
+            .parse("infixl 6 <+> a <+> b")
+            .print(&mut printed);
+        assert_eq!(printed, "(a <+> b)");
+
+        // but they have to be something that can be an operator
+        for operator in [
+            "not an operator",
+            "(",
+            "{",
+            ",",
+            "->",
+            "_",
+            "=",
+            "?",
+            "~".repeat(17).as_str(),
+        ] {
+            let json = format!(
+                r#"{{"kind":"binary","left":{{"kind":"name","name":"a"}},"operator":{operator:?},"right":{{"kind":"name","name":"b"}}}}"#
+            );
+            let error = serde_json::from_str::<Expr>(&json).unwrap_err();
+            assert!(error.to_string().contains("is not an operator"), "{error}");
+        }
+    }
 }